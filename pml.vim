@@ -1,39 +1,78 @@
 " Vim syntax file
 " Language: Primal Language
 " Maintainer: Adamocho
-" Latest Revision: 15 November 2024
+" Latest Revision: 18 October 2026
 
 if exists("b:current_syntax")
   finish
 endif
 
+" The dialect comes from the `#!dialect` pragma on the first line,
+" or else from g:primal_dialect for programs which get it from `--dialect`
+let s:dialect = matchstr(getline(1), '^#!dialect\s\+\zs\S\+')
+if s:dialect == ''
+  let s:dialect = get(g:, 'primal_dialect', 'strict')
+endif
+
 
-" Comments
+" Comments, and the pragma which looks like one
 syn match comment "#.*$"
+syn match dialectPragma '\%1l^#!dialect\>.*$'
 
 " Integer with - + or nothing in front
 syn match number '\d\+'
 syn match number '[-+]\d\+'
 
-" Strings
-syn region string start='"' end='"'
+" Float, digits on both sides of the point
+syn match float '[-+]\=\d\+\.\d\+'
+
+" Strings, `\"` does not end one and they may go over several lines
+syn region string start='"' skip='\\\\\|\\"' end='"' contains=stringEscape
+syn match stringEscape '\\\([nt"\\]\|u{\x\+}\)' contained
+
+" Keywords, only in capitals unless the dialect takes them in any case
+if s:dialect != 'strict'
+  syn case ignore
+endif
 
-" Keywords
-syn keyword basicKeywords LET PRINT INPUT
+syn keyword basicKeywords LET PRINT INPUT DIM
 syn keyword truthValues true false
 syn keyword condKeywords AND NOT OR
-syn keyword ifKeywords IF ENDIF THEN
+syn keyword ifKeywords IF ELSEIF ELSE ENDIF THEN
 syn keyword whileKeywords WHILE DO ENDWHILE
+syn keyword forKeywords FOR TO STEP NEXT
+syn keyword jumpKeywords BREAK CONTINUE
+syn keyword subKeywords SUB ENDSUB CALL RETURN
+syn keyword gotoKeywords GOTO GOSUB
+syn match lineNumber '^\s*\d\+'
+
+" String functions, a `$` is not part of a keyword
+syn match stringFunctions '\<\(LEN\|VAL\|MID\$\|LEFT\$\|RIGHT\$\|STR\$\)'
+
+" REM comments of the classic dialect go up to the end of the line
+if s:dialect == 'classic'
+  syn region remComment start='\<REM\>' end='$'
+endif
 
 " Highlighting
 hi def link comment         Comment
+hi def link dialectPragma   PreProc
+hi def link remComment      Comment
 hi def link basicKeywords   Statement
 hi def link condKeywords    Label
 hi def link string          String
+hi def link stringEscape    SpecialChar
 hi def link number          Number
+hi def link float           Float
 hi def link truthValues     Boolean
 hi def link ifKeywords      Conditional 
 hi def link whileKeywords   Repeat
+hi def link forKeywords     Repeat
+hi def link jumpKeywords    Keyword
+hi def link subKeywords     Function
+hi def link gotoKeywords    Keyword
+hi def link stringFunctions Function
+hi def link lineNumber      LineNr
 
 
 " Setting the file type
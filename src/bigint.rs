@@ -0,0 +1,346 @@
+// Whole numbers of any size for `--bigint`. The interpreter uses this module as it is and the
+// emitter pastes it into the generated program, so it depends on nothing but std.
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::str::FromStr;
+
+// every limb holds nine decimal digits, which keeps printing and parsing simple
+const BASE: u64 = 1_000_000_000;
+const DIGITS_PER_LIMB: usize = 9;
+
+/// A signed integer without bounds, in base 10^9 with the least significant limb first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrimalInt {
+    // zero is never negative
+    is_negative: bool,
+    // no zero limbs at the end, so zero has none at all
+    limbs: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsePrimalIntError;
+
+impl PrimalInt {
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.is_negative
+    }
+
+    fn new(is_negative: bool, mut limbs: Vec<u32>) -> PrimalInt {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let is_negative = is_negative && !limbs.is_empty();
+
+        PrimalInt { is_negative, limbs }
+    }
+
+    // a + b of the magnitudes
+    fn add_limbs(left: &[u32], right: &[u32]) -> Vec<u32> {
+        let mut limbs = Vec::with_capacity(left.len().max(right.len()) + 1);
+        let mut carry = 0;
+
+        for index in 0..left.len().max(right.len()) {
+            let sum = carry + u64::from(*left.get(index).unwrap_or(&0)) + u64::from(*right.get(index).unwrap_or(&0));
+            limbs.push((sum % BASE) as u32);
+            carry = sum / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        limbs
+    }
+
+    // a - b of the magnitudes, a must not be smaller than b
+    fn sub_limbs(left: &[u32], right: &[u32]) -> Vec<u32> {
+        let mut limbs = Vec::with_capacity(left.len());
+        let mut borrow = 0;
+
+        for (index, limb) in left.iter().enumerate() {
+            let subtrahend = i64::from(*right.get(index).unwrap_or(&0)) + borrow;
+            let mut difference = i64::from(*limb) - subtrahend;
+
+            borrow = 0;
+            if difference < 0 {
+                difference += BASE as i64;
+                borrow = 1;
+            }
+            limbs.push(difference as u32);
+        }
+        limbs
+    }
+
+    fn mul_limbs(left: &[u32], right: &[u32]) -> Vec<u32> {
+        let mut limbs = vec![0u64; left.len() + right.len()];
+
+        for (i, left) in left.iter().enumerate() {
+            let mut carry = 0;
+
+            for (j, right) in right.iter().enumerate() {
+                let product = limbs[i + j] + u64::from(*left) * u64::from(*right) + carry;
+                limbs[i + j] = product % BASE;
+                carry = product / BASE;
+            }
+            limbs[i + right.len()] += carry;
+        }
+        limbs.into_iter().map(|limb| limb as u32).collect()
+    }
+
+    fn cmp_limbs(left: &[u32], right: &[u32]) -> Ordering {
+        left.len()
+            .cmp(&right.len())
+            .then_with(|| left.iter().rev().cmp(right.iter().rev()))
+    }
+
+    // long division, one limb of the quotient at a time, found with a binary search
+    fn div_rem_limbs(left: &[u32], right: &[u32]) -> (Vec<u32>, Vec<u32>) {
+        let mut quotient = vec![0; left.len()];
+        let mut remainder: Vec<u32> = vec![];
+
+        for index in (0..left.len()).rev() {
+            remainder.insert(0, left[index]);
+            remainder = PrimalInt::new(false, remainder).limbs;
+
+            let (mut low, mut high) = (0, BASE as u32 - 1);
+            while low < high {
+                let middle = low + (high - low).div_ceil(2);
+                let product = PrimalInt::new(false, Self::mul_limbs(right, &[middle])).limbs;
+
+                match Self::cmp_limbs(&product, &remainder) {
+                    Ordering::Greater => high = middle - 1,
+                    _ => low = middle,
+                }
+            }
+
+            let product = PrimalInt::new(false, Self::mul_limbs(right, &[low])).limbs;
+            remainder = PrimalInt::new(false, Self::sub_limbs(&remainder, &product)).limbs;
+            quotient[index] = low;
+        }
+        (quotient, remainder)
+    }
+
+    // both rounded towards zero, like i32
+    fn div_rem(&self, other: &PrimalInt) -> (PrimalInt, PrimalInt) {
+        assert!(!other.is_zero(), "attempt to divide by zero");
+
+        let (quotient, remainder) = Self::div_rem_limbs(&self.limbs, &other.limbs);
+
+        (
+            PrimalInt::new(self.is_negative != other.is_negative, quotient),
+            PrimalInt::new(self.is_negative, remainder),
+        )
+    }
+}
+
+impl From<i32> for PrimalInt {
+    fn from(value: i32) -> PrimalInt {
+        PrimalInt::from(i64::from(value))
+    }
+}
+
+impl From<i64> for PrimalInt {
+    fn from(value: i64) -> PrimalInt {
+        let mut magnitude = value.unsigned_abs();
+        let mut limbs = vec![];
+
+        while magnitude > 0 {
+            limbs.push((magnitude % BASE) as u32);
+            magnitude /= BASE;
+        }
+        PrimalInt::new(value < 0, limbs)
+    }
+}
+
+impl TryFrom<&PrimalInt> for i32 {
+    type Error = ();
+
+    fn try_from(value: &PrimalInt) -> Result<i32, ()> {
+        if value.limbs.len() > 2 {
+            return Err(());
+        }
+
+        let magnitude = value.limbs.iter().rev().fold(0i64, |total, limb| total * BASE as i64 + i64::from(*limb));
+        let value = if value.is_negative { -magnitude } else { magnitude };
+
+        i32::try_from(value).map_err(|_| ())
+    }
+}
+
+// the nearest f64, as if the digits were written with a point
+impl From<PrimalInt> for f64 {
+    fn from(value: PrimalInt) -> f64 {
+        value.to_string().parse().unwrap_or(f64::NAN)
+    }
+}
+
+impl FromStr for PrimalInt {
+    type Err = ParsePrimalIntError;
+
+    fn from_str(text: &str) -> Result<PrimalInt, ParsePrimalIntError> {
+        let (is_negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+
+        if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return Err(ParsePrimalIntError);
+        }
+
+        // nine digits at a time, from the end
+        let limbs = digits
+            .as_bytes()
+            .rchunks(DIGITS_PER_LIMB)
+            .map(|chunk| chunk.iter().fold(0, |limb, digit| limb * 10 + u32::from(digit - b'0')))
+            .collect();
+
+        Ok(PrimalInt::new(is_negative, limbs))
+    }
+}
+
+impl fmt::Display for PrimalInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some((last, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+
+        if self.is_negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", last)?;
+
+        for limb in rest.iter().rev() {
+            write!(f, "{:09}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for PrimalInt {
+    fn cmp(&self, other: &PrimalInt) -> Ordering {
+        match (self.is_negative, other.is_negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => Self::cmp_limbs(&self.limbs, &other.limbs),
+            (true, true) => Self::cmp_limbs(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for PrimalInt {
+    fn partial_cmp(&self, other: &PrimalInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for PrimalInt {
+    type Output = PrimalInt;
+
+    fn neg(self) -> PrimalInt {
+        PrimalInt::new(!self.is_negative, self.limbs)
+    }
+}
+
+impl Add for PrimalInt {
+    type Output = PrimalInt;
+
+    fn add(self, other: PrimalInt) -> PrimalInt {
+        if self.is_negative == other.is_negative {
+            return PrimalInt::new(self.is_negative, PrimalInt::add_limbs(&self.limbs, &other.limbs));
+        }
+
+        // the sign of the bigger magnitude wins
+        match PrimalInt::cmp_limbs(&self.limbs, &other.limbs) {
+            Ordering::Less => PrimalInt::new(other.is_negative, PrimalInt::sub_limbs(&other.limbs, &self.limbs)),
+            _ => PrimalInt::new(self.is_negative, PrimalInt::sub_limbs(&self.limbs, &other.limbs)),
+        }
+    }
+}
+
+impl Sub for PrimalInt {
+    type Output = PrimalInt;
+
+    fn sub(self, other: PrimalInt) -> PrimalInt {
+        self + -other
+    }
+}
+
+impl Mul for PrimalInt {
+    type Output = PrimalInt;
+
+    fn mul(self, other: PrimalInt) -> PrimalInt {
+        PrimalInt::new(self.is_negative != other.is_negative, PrimalInt::mul_limbs(&self.limbs, &other.limbs))
+    }
+}
+
+impl Div for PrimalInt {
+    type Output = PrimalInt;
+
+    fn div(self, other: PrimalInt) -> PrimalInt {
+        self.div_rem(&other).0
+    }
+}
+
+impl Rem for PrimalInt {
+    type Output = PrimalInt;
+
+    fn rem(self, other: PrimalInt) -> PrimalInt {
+        self.div_rem(&other).1
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(text: &str) -> PrimalInt {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_print() {
+        assert_eq!(big("0").to_string(), "0");
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(big("000123").to_string(), "123");
+        assert_eq!(big("-1000000000000000000001").to_string(), "-1000000000000000000001");
+        assert_eq!(PrimalInt::from(i32::MIN).to_string(), "-2147483648");
+        assert_eq!("12a".parse::<PrimalInt>(), Err(ParsePrimalIntError));
+        assert_eq!("-".parse::<PrimalInt>(), Err(ParsePrimalIntError));
+    }
+
+    #[test]
+    fn calculate_past_i32() {
+        let max = PrimalInt::from(i32::MAX);
+
+        assert_eq!((max.clone() + PrimalInt::from(1)).to_string(), "2147483648");
+        assert_eq!((max.clone() * max.clone()).to_string(), "4611686014132420609");
+        assert_eq!((PrimalInt::from(5) - big("1000000000")).to_string(), "-999999995");
+        assert_eq!((big("-1000000000") + big("999999999")).to_string(), "-1");
+        assert_eq!((big("123456789123456789123456789") - big("123456789123456789123456789")).to_string(), "0");
+    }
+
+    #[test]
+    fn divide_towards_zero() {
+        let dividend = big("1000000000000000000000007");
+
+        assert_eq!((dividend.clone() / big("1000000000000")).to_string(), "1000000000000");
+        assert_eq!((dividend.clone() % big("1000000000000")).to_string(), "7");
+        assert_eq!((PrimalInt::from(-7) / PrimalInt::from(2)).to_string(), "-3");
+        assert_eq!((PrimalInt::from(-7) % PrimalInt::from(2)).to_string(), "-1");
+        assert_eq!((PrimalInt::from(7) % PrimalInt::from(-2)).to_string(), "1");
+    }
+
+    #[test]
+    fn compare_and_convert() {
+        assert!(big("-5") < big("-4"));
+        assert!(big("-10000000000") < PrimalInt::from(0));
+        assert!(big("10000000000") > PrimalInt::from(i32::MAX));
+        assert_eq!(i32::try_from(&PrimalInt::from(i32::MIN)), Ok(i32::MIN));
+        assert_eq!(i32::try_from(&big("2147483648")), Err(()));
+        assert_eq!(f64::from(big("-12345678901234567890")), -12345678901234567890.0);
+    }
+}
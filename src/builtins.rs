@@ -0,0 +1,62 @@
+use crate::semantic::Type;
+
+/// A string function every program has, called like a SUB which returns a value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Len,
+    Mid,
+    Left,
+    Right,
+    Str,
+    Val,
+}
+
+impl Builtin {
+    pub fn find(name: &str) -> Option<Builtin> {
+        match name {
+            "LEN" => Some(Builtin::Len),
+            "MID$" => Some(Builtin::Mid),
+            "LEFT$" => Some(Builtin::Left),
+            "RIGHT$" => Some(Builtin::Right),
+            "STR$" => Some(Builtin::Str),
+            "VAL" => Some(Builtin::Val),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Len => "LEN",
+            Builtin::Mid => "MID$",
+            Builtin::Left => "LEFT$",
+            Builtin::Right => "RIGHT$",
+            Builtin::Str => "STR$",
+            Builtin::Val => "VAL",
+        }
+    }
+
+    /// Names and types of the parameters, STR$ takes a Float as well as a Number.
+    pub fn parameters(self) -> &'static [(&'static str, Type)] {
+        match self {
+            Builtin::Len | Builtin::Val => &[("text", Type::String)],
+            Builtin::Mid => &[("text", Type::String), ("start", Type::Number), ("length", Type::Number)],
+            Builtin::Left | Builtin::Right => &[("text", Type::String), ("count", Type::Number)],
+            Builtin::Str => &[("value", Type::Number)],
+        }
+    }
+
+    /// How many of the parameters may be left out at the end, the length of MID$.
+    pub fn optional(self) -> usize {
+        match self {
+            Builtin::Mid => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn returns(self) -> Type {
+        match self {
+            Builtin::Len | Builtin::Val => Type::Number,
+            Builtin::Mid | Builtin::Left | Builtin::Right | Builtin::Str => Type::String,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Location of a piece of source code. The offset is in bytes from the start
+/// of the file, lines and columns (counted in characters) start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Span {
+    pub fn new(offset: usize, line: usize, column: usize, length: usize) -> Span {
+        Span { offset, line, column, length }
+    }
+
+    /// Span from the start of this one to the end of the other one, both on the same line.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            length: (other.column + other.length).saturating_sub(self.column),
+            ..self
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Span) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message, span }
+    }
+
+    pub fn warning(message: String, span: Span) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, message, span }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render in the style of rustc:
+    ///
+    /// ```text
+    /// error: token not recognized: `@`
+    ///  --> test.roq:3:9
+    ///   |
+    /// 3 | LET x = @
+    ///   |         ^
+    /// ```
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut output = format!("{}: {}\n", self.severity, self.message);
+
+        // line 0 stands for no particular place in the file
+        let Some(line) = self.span.line.checked_sub(1).and_then(|index| source.lines().nth(index)) else {
+            output.push_str(&format!(" --> {}\n", file_name));
+            return output;
+        };
+
+        let line_number = self.span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        output.push_str(&format!("{}--> {}:{}:{}\n", gutter, file_name, self.span.line, self.span.column));
+        output.push_str(&format!("{} |\n", gutter));
+        output.push_str(&format!("{} | {}\n", line_number, line));
+        output.push_str(&format!("{} | {}\n", gutter, Self::underline(self.span)));
+
+        output
+    }
+
+    fn underline(span: Span) -> String {
+        " ".repeat(span.column - 1) + &"^".repeat(span.length.max(1))
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
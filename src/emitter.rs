@@ -1,115 +1,1088 @@
 use std::collections::HashMap;
-use crate::lexer::{Lexer, Token};
-use crate::parser::{Condition, Expression, Statement};
+use crate::builtins::Builtin;
+use crate::lexer::{Lexer, Token, RESERVED_PREFIX};
+use crate::parser::{Condition, Expression, Separator, Statement, ZONE_WIDTH};
+use crate::semantic::{NumberMode, SymbolTable, Symbols, Type};
 
 #[derive(Debug)]
 pub struct Emitter {
     statements: Vec<Statement>,
+    symbols: Symbols,
+    // the SUB being written, None for main
+    scope: Option<String>,
+    // support functions written after main, each one only once
+    helpers: Vec<&'static str>,
+    // nested FOR loops need their own temporary variables
+    loop_depth: usize,
+    // what BREAK and CONTINUE become in each of the loops around
+    loops: Vec<(String, String)>,
+    // with jumps, main becomes a loop over states, one per line number and one after every GOSUB
+    jump_targets: HashMap<i32, usize>,
+    state: usize,
 }
 
+// what a state of main does, IFs with a GOSUB inside are taken apart into jumps
+enum Step<'a> {
+    Run(&'a Statement),
+    // to the state of the next branch when the condition is false
+    JumpUnless(&'a Condition, usize),
+    Jump(usize),
+}
+
+// asks until the answer parses into the variable's type
+const INPUT_HELPER: &str = r#"fn primal_input<T: std::str::FromStr>(message: &str, expected: &str, line: usize) -> T {
+    loop {
+        println!("{}", message);
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).expect("Failed to read user input") == 0 {
+            eprintln!("Runtime error at line {}: expected {}, but the input has ended", line, expected);
+            std::process::exit(1);
+        }
+
+        match answer.trim().parse() {
+            Ok(value) => return value,
+            Err(_) => println!("Please enter {}.", expected),
+        }
+    }
+}"#;
+
+// whether a FOR loop counter is still between its start and its end
+const FOR_HELPER: &str = r#"fn primal_in_range(value: i32, step: i32, end: i32, line: usize) -> bool {
+    match step {
+        0 => {
+            eprintln!("Runtime error at line {}: the STEP of a FOR loop must not be 0", line);
+            std::process::exit(1);
+        }
+        step if step > 0 => value <= end,
+        _ => value >= end,
+    }
+}"#;
+
+// the length of an array from the last index given to DIM
+const DIM_HELPER: &str = r#"fn primal_dim(last: i32, line: usize) -> usize {
+    if last < 0 {
+        eprintln!("Runtime error at line {}: the last index of an array must not be negative, found {}", line, last);
+        std::process::exit(1);
+    }
+    last as usize + 1
+}"#;
+
+// where a primal index points in a Rust Vec, checked before Rust would panic
+const INDEX_HELPER: &str = r#"fn primal_index(index: i32, length: usize, array: &str, line: usize) -> usize {
+    match usize::try_from(index) {
+        Ok(position) if position < length => position,
+        _ => {
+            eprintln!("Runtime error at line {}: index {} is out of bounds for `{}`, which has {} elements", line, index, array, length);
+            std::process::exit(1);
+        }
+    }
+}"#;
+
+// FOR loops, array sizes and indices stay i32 with `--bigint`
+const SMALL_HELPER: &str = r#"fn primal_small(value: PrimalInt, line: usize) -> i32 {
+    i32::try_from(&value).unwrap_or_else(|_| {
+        eprintln!("Runtime error at line {}: {} is too big, FOR loops, array sizes and indices have to fit in 32 bits", line, value);
+        std::process::exit(1);
+    })
+}"#;
+
+// i32 arithmetic stops the program where Rust would panic or wrap around
+const CHECKED_HELPER: &str = r#"fn primal_checked(result: Option<i32>, line: usize) -> i32 {
+    result.unwrap_or_else(|| {
+        eprintln!("Runtime error at line {}: integer overflow", line);
+        std::process::exit(1);
+    })
+}"#;
+
+// zero is checked first, i32::MIN / -1 is left to primal_checked
+const DIVIDE_HELPER: &str = r#"fn primal_divide(left: i32, right: i32, line: usize) -> i32 {
+    if right == 0 {
+        eprintln!("Runtime error at line {}: division by zero", line);
+        std::process::exit(1);
+    }
+    primal_checked(left.checked_div(right), line)
+}"#;
+
+const REMAINDER_HELPER: &str = r#"fn primal_remainder(left: i32, right: i32, line: usize) -> i32 {
+    if right == 0 {
+        eprintln!("Runtime error at line {}: division by zero", line);
+        std::process::exit(1);
+    }
+    primal_checked(left.checked_rem(right), line)
+}"#;
+
+// with `--bigint` nothing overflows, but dividing by zero still has to be reported
+const BIG_DIVIDE_HELPER: &str = r#"fn primal_divide(left: PrimalInt, right: PrimalInt, line: usize) -> PrimalInt {
+    if right.is_zero() {
+        eprintln!("Runtime error at line {}: division by zero", line);
+        std::process::exit(1);
+    }
+    left / right
+}"#;
+
+const BIG_REMAINDER_HELPER: &str = r#"fn primal_remainder(left: PrimalInt, right: PrimalInt, line: usize) -> PrimalInt {
+    if right.is_zero() {
+        eprintln!("Runtime error at line {}: division by zero", line);
+        std::process::exit(1);
+    }
+    left % right
+}"#;
+
+// the string functions, with what primal_strings finds wrong reported like the other runtime errors
+const LEN_HELPER: &str = r#"fn primal_len(text: String) -> i32 {
+    primal_strings::len(&text)
+}"#;
+
+const MID_HELPER: &str = r#"fn primal_mid(text: String, start: i32, length: Option<i32>, line: usize) -> String {
+    primal_strings::mid(&text, start, length).unwrap_or_else(|message| {
+        eprintln!("Runtime error at line {}: {}", line, message);
+        std::process::exit(1);
+    })
+}"#;
+
+const LEFT_HELPER: &str = r#"fn primal_left(text: String, count: i32, line: usize) -> String {
+    primal_strings::left(&text, count).unwrap_or_else(|message| {
+        eprintln!("Runtime error at line {}: {}", line, message);
+        std::process::exit(1);
+    })
+}"#;
+
+const RIGHT_HELPER: &str = r#"fn primal_right(text: String, count: i32, line: usize) -> String {
+    primal_strings::right(&text, count).unwrap_or_else(|message| {
+        eprintln!("Runtime error at line {}: {}", line, message);
+        std::process::exit(1);
+    })
+}"#;
+
+// the same text PRINT would write
+const STR_HELPER: &str = r#"fn primal_str<T: std::fmt::Display>(value: T) -> String {
+    value.to_string()
+}"#;
+
+const VAL_HELPER: &str = r#"fn primal_val(text: String, line: usize) -> i32 {
+    primal_strings::val(&text).unwrap_or_else(|message| {
+        eprintln!("Runtime error at line {}: {}", line, message);
+        std::process::exit(1);
+    })
+}"#;
+
+// with `--bigint` any number of digits fits
+const BIG_VAL_HELPER: &str = r#"fn primal_val(text: String) -> PrimalInt {
+    match primal_strings::leading_number(&text) {
+        Some(digits) => digits.parse().unwrap(),
+        None => PrimalInt::default(),
+    }
+}"#;
+
+// the helpers which need primal_strings next to them
+const STRINGS_HELPERS: [&str; 6] = [LEN_HELPER, MID_HELPER, LEFT_HELPER, RIGHT_HELPER, VAL_HELPER, BIG_VAL_HELPER];
+
+// pads a PRINT up to the next tab zone, ZONE_WIDTH characters each; the width is filled in when it is written,
+// from the same constant the interpreter uses
+const ZONE_HELPER: &str = r#"fn primal_zone(text: impl std::fmt::Display) -> String {
+    let text = text.to_string();
+    let width = text.chars().count();
+
+    text + &" ".repeat(ZONE_WIDTH - width % ZONE_WIDTH)
+}"#;
+
+// Rust keywords, reserved ones included, which are fine primal names; they are written as raw identifiers
+const RUST_KEYWORDS: [&str; 52] = [
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+    "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+    "trait", "true", "type", "unsafe", "use", "where", "while", "async", "await", "dyn", "abstract", "become", "box",
+    "do", "final", "macro", "override", "priv", "typeof", "unsized", "virtual", "yield", "try", "gen",
+];
+
+// the ones which cannot be raw identifiers either, and `_`, which is no name at all in Rust
+const UNRAW_KEYWORDS: [&str; 5] = ["crate", "self", "Self", "super", "_"];
+
+// the prelude's enum variants, a `let` with one of them would be a pattern matching it
+const PRELUDE_VARIANTS: [&str; 4] = ["Some", "None", "Ok", "Err"];
+
+// the same PrimalInt the interpreter calculates with, without its tests
+const BIGINT_MODULE: &str = include_str!("bigint.rs");
+
+// and the same string functions
+const STRINGS_MODULE: &str = include_str!("strings.rs");
+
 impl Emitter {
-    pub fn new(statements: Vec<Statement>) -> Emitter {
+    pub fn new(statements: Vec<Statement>, symbols: Symbols) -> Emitter {
         Emitter {
             statements,
+            symbols,
+            scope: None,
+            helpers: vec![],
+            loop_depth: 0,
+            loops: vec![],
+            jump_targets: HashMap::new(),
+            state: 0,
         }
     }
 
     pub fn emit(&mut self) -> Vec<String> {
-        let mut used_variables: HashMap<String, u8> = HashMap::new();
+        let statements = std::mem::take(&mut self.statements);
 
-        let mut lines = self.statements
-            .iter()
-            .flat_map(|statement| Self::evaluate(statement, &mut used_variables))
-            .collect::<Vec<String>>();
+        let mut lines = vec![
+            "#[allow(unused_mut, unused_assignments, unused_variables)]".to_string(),
+            "fn main() {".to_string(),
+        ];
 
-        lines.insert(0, "#[allow(unused_mut)]".to_string());
-        lines.insert(1, "fn main() {".to_string());
+        lines.append(&mut self.declarations(&self.symbols.variables, &[]));
+        lines.append(&mut self.array_declarations(&self.symbols.arrays));
+
+        if Self::jumps(&statements) {
+            lines[0] = "#[allow(unused_mut, unused_assignments, unused_variables, unreachable_code)]".to_string();
+            lines.append(&mut self.emit_dispatch(&statements));
+        } else {
+            for statement in &statements {
+                let mut statement_lines = self.evaluate(statement);
+                lines.append(&mut statement_lines);
+            }
+        }
         
         lines.push("}".to_string());
 
+        for statement in &statements {
+            if let Statement::Sub { name, parameters, sub_body } = statement {
+                lines.push(String::new());
+                lines.append(&mut self.emit_sub(name, parameters, sub_body));
+            }
+        }
+
+        for helper in &self.helpers {
+            lines.push(String::new());
+            lines.push(helper.replace("ZONE_WIDTH", &ZONE_WIDTH.to_string()));
+        }
+
+        if self.symbols.numbers == NumberMode::Big {
+            lines.append(&mut Self::module("primal_int", BIGINT_MODULE));
+            lines.push("use primal_int::PrimalInt;".to_string());
+        }
+
+        if self.helpers.iter().any(|helper| STRINGS_HELPERS.contains(helper)) {
+            lines.append(&mut Self::module("primal_strings", STRINGS_MODULE));
+        }
+
+        self.statements = statements;
+
+        lines
+    }
+
+    // one of primal's own source files, without its tests
+    fn module(name: &str, source: &str) -> Vec<String> {
+        let module = source.split("\n\n\n#[cfg(test)]").next().unwrap_or(source);
+
+        vec![
+            String::new(),
+            "#[allow(dead_code)]".to_string(),
+            format!("mod {} {{", name),
+            module.trim_end().to_string(),
+            "}".to_string(),
+        ]
+    }
+
+    // declare everything up front, primal variables outlive the block they were set in
+    fn declarations(&self, variables: &SymbolTable, parameters: &[String]) -> Vec<String> {
+        let mut variables: Vec<(&String, &Type)> = variables
+            .iter()
+            .filter(|(variable, _)| !parameters.contains(variable))
+            .collect();
+        variables.sort_by_key(|(variable, _)| *variable);
+
+        variables
+            .into_iter()
+            .map(|(variable, variable_type)| format!(
+                "let mut {}: {} = {};",
+                Self::rust_name(variable),
+                self.rust_type(*variable_type),
+                self.default_value(*variable_type)
+            ))
+            .collect()
+    }
+
+    fn array_declarations(&self, arrays: &SymbolTable) -> Vec<String> {
+        let mut arrays: Vec<(&String, &Type)> = arrays.iter().collect();
+        arrays.sort_by_key(|(array, _)| *array);
+
+        arrays
+            .into_iter()
+            .map(|(array, element_type)| format!("let mut {}: Vec<{}> = Vec::new();", Self::rust_name(array), self.rust_type(*element_type)))
+            .collect()
+    }
+
+    // loop { match pc { ... } }, where every state falls through to the next one unless it jumps
+    fn emit_dispatch(&mut self, statements: &[Statement]) -> Vec<String> {
+        let mut states: Vec<Vec<Step>> = vec![vec![]];
+        self.split_states(statements, &mut states);
+
+        let mut lines = vec![
+            "let mut primal_pc: usize = 0;".to_string(),
+            "let mut primal_returns: Vec<usize> = Vec::new();".to_string(),
+            "'primal_dispatch: loop {".to_string(),
+            "match primal_pc {".to_string(),
+        ];
+
+        for (index, state) in states.into_iter().enumerate() {
+            self.state = index;
+
+            lines.push(format!("{} => {{", index));
+            for step in state {
+                match step {
+                    Step::Run(statement) => lines.append(&mut self.evaluate(statement)),
+                    Step::JumpUnless(condition, target) => {
+                        lines.push(format!("if !({}) {{", self.convert_condition_to_string(condition)));
+                        lines.push(format!("primal_pc = {};", target));
+                        lines.push("continue 'primal_dispatch;".to_string());
+                        lines.push("}".to_string());
+                    }
+                    Step::Jump(target) => {
+                        lines.push(format!("primal_pc = {};", target));
+                        lines.push("continue 'primal_dispatch;".to_string());
+                    }
+                }
+            }
+            lines.push("}".to_string());
+        }
+
+        lines.push("_ => break,".to_string());
+        lines.push("}".to_string());
+        lines.push("primal_pc += 1;".to_string());
+        lines.push("}".to_string());
+
         lines
     }
 
-    fn evaluate(statement: &Statement, used_variables: &mut HashMap<String, u8>) -> Vec<String> {
+    fn split_states<'a>(&mut self, statements: &'a [Statement], states: &mut Vec<Vec<Step<'a>>>) {
+        for statement in statements {
+            match statement {
+                Statement::Label { number } => {
+                    let is_empty = states
+                        .last()
+                        .is_some_and(|state| state.iter().all(|step| matches!(step, Step::Run(Statement::Label { .. }))));
+
+                    if !is_empty {
+                        states.push(vec![]);
+                    }
+                    self.jump_targets.insert(*number, states.len() - 1);
+                    states.last_mut().unwrap().push(Step::Run(statement));
+                }
+                // RETURN comes back right after the GOSUB
+                Statement::Gosub { .. } => {
+                    states.last_mut().unwrap().push(Step::Run(statement));
+                    states.push(vec![]);
+                }
+                // nor can it come back into the middle of an `if`, so the branches become states of their own
+                Statement::If { condition_tree, if_body, elseif_branches, else_body } if Self::gosubs(std::slice::from_ref(statement)) => {
+                    let branches = std::iter::once((condition_tree, if_body))
+                        .chain(elseif_branches.iter().map(|(condition, body)| (condition, body)));
+                    let mut exits = vec![];
+
+                    for (condition, body) in branches {
+                        let skip = (states.len() - 1, states.last().unwrap().len());
+                        states.last_mut().unwrap().push(Step::JumpUnless(condition, 0));
+                        self.split_states(body, states);
+
+                        exits.push((states.len() - 1, states.last().unwrap().len()));
+                        states.last_mut().unwrap().push(Step::Jump(0));
+                        states.push(vec![]);
+
+                        let next = states.len() - 1;
+                        if let Step::JumpUnless(_, target) = &mut states[skip.0][skip.1] {
+                            *target = next;
+                        }
+                    }
+
+                    self.split_states(else_body, states);
+                    states.push(vec![]);
+
+                    let end = states.len() - 1;
+                    for (state, position) in exits {
+                        if let Step::Jump(target) = &mut states[state][position] {
+                            *target = end;
+                        }
+                    }
+                }
+                _ => states.last_mut().unwrap().push(Step::Run(statement)),
+            }
+        }
+    }
+
+    // whether main needs the states of emit_dispatch
+    fn jumps(statements: &[Statement]) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Goto { .. } | Statement::Gosub { .. } | Statement::GosubReturn { .. } => true,
+            Statement::If { if_body, elseif_branches, else_body, .. } => {
+                Self::jumps(if_body)
+                    || elseif_branches.iter().any(|(_, body)| Self::jumps(body))
+                    || Self::jumps(else_body)
+            }
+            Statement::While { while_body: body, .. } | Statement::For { for_body: body, .. } => Self::jumps(body),
+            _ => false,
+        })
+    }
+
+    // whether a RETURN comes back into these statements, where GOSUB can only be inside IFs
+    fn gosubs(statements: &[Statement]) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Gosub { .. } => true,
+            Statement::If { if_body, elseif_branches, else_body, .. } => {
+                Self::gosubs(if_body)
+                    || elseif_branches.iter().any(|(_, body)| Self::gosubs(body))
+                    || Self::gosubs(else_body)
+            }
+            _ => false,
+        })
+    }
+
+    fn emit_sub(&mut self, name: &Token, parameters: &[Token], sub_body: &[Statement]) -> Vec<String> {
+        let name = name.to_string();
+        let signature = self.symbols.subs[&name].clone();
+        let parameters: Vec<String> = parameters.iter().map(Token::to_string).collect();
+
+        let parameter_list: Vec<String> = parameters
+            .iter()
+            .zip(&signature.parameters)
+            .map(|(parameter, parameter_type)| format!("mut {}: {}", Self::rust_name(parameter), self.rust_type(*parameter_type)))
+            .collect();
+        let returns = match signature.returns {
+            Some(returns) => format!(" -> {}", self.rust_type(returns)),
+            None => String::new(),
+        };
+
+        let mut lines = vec![
+            "#[allow(unused_mut, unused_assignments, unused_variables, unreachable_code, dead_code)]".to_string(),
+            format!("fn {}({}){} {{", Self::rust_name(&name), parameter_list.join(", "), returns),
+        ];
+        lines.append(&mut self.declarations(&signature.variables, &parameters));
+        lines.append(&mut self.array_declarations(&signature.arrays));
+
+        self.scope = Some(name);
+        lines.append(&mut self.evaluate_block(sub_body));
+        self.scope = None;
+
+        // falling off the end returns the default value
+        if let Some(returns) = signature.returns {
+            lines.push(self.default_value(returns).to_string());
+        }
+        lines.push("}".to_string());
+
+        lines
+    }
+
+    fn scope_symbols(&self) -> &SymbolTable {
+        self.symbols.variables_in(self.scope.as_deref())
+    }
+
+    fn scope_arrays(&self) -> &SymbolTable {
+        self.symbols.arrays_in(self.scope.as_deref())
+    }
+
+    fn type_of(&self, expression: &Expression) -> Option<Type> {
+        self.symbols.type_of(expression, self.scope.as_deref())
+    }
+
+    fn evaluate(&mut self, statement: &Statement) -> Vec<String> {
         let mut output: Vec<String> = vec![];
 
         match statement {
-            Statement::Print { value } => {
-                let variable_value = Self::unwrap_value_token(value.clone());
+            Statement::Print { items } => output.push(self.convert_print_to_string(items)),
+            Statement::Let { identifier, expression_tree } => {
+                let target = self.scope_symbols().get(&identifier.to_string()).copied();
+                let expr = self.convert_widened_to_string(expression_tree, target);
 
-                output.push("println!(\"{}\", ".to_owned() + &variable_value + " );");
+                output.push(Self::unwrap_value_token(identifier.clone()) + " = " + &expr + ";");
             }
-            Statement::Let { identifier, expression_tree } => {
-                let variable = Self::unwrap_value_token(identifier.clone());
-                let expr = Self::convert_expression_to_string(expression_tree);
+            Statement::Dim { identifier, size } => {
+                let array = Self::unwrap_value_token(identifier.clone());
+                let element = self.default_value(self.scope_arrays()[&identifier.to_string()]);
 
-                if used_variables.get(&variable).is_some() {
-                    output.push(variable + " = " + &expr + ";");
-                } else {
-                    output.push("let mut ".to_owned() + &variable + " = " + &expr + ";");
-                    used_variables.insert(variable, 0);
-                }
+                self.use_helper(DIM_HELPER);
+                output.push(format!(
+                    "{} = vec![{}; primal_dim({}, {})];",
+                    array,
+                    element,
+                    self.convert_small_to_string(size),
+                    size.span().line
+                ));
+            }
+            Statement::LetElement { identifier, index, expression_tree } => {
+                let array = Self::unwrap_value_token(identifier.clone());
+                let target = self.scope_arrays().get(&identifier.to_string()).copied();
+
+                // the value first, like the interpreter, and the index apart so that it can borrow the array
+                output.push(format!("let primal_element = {};", self.convert_widened_to_string(expression_tree, target)));
+                output.push(format!("let primal_position = {};", self.convert_position_to_string(identifier, index)));
+                output.push(format!("{}[primal_position] = primal_element;", array));
             }
-            Statement::If { condition_tree, if_body } => {
-                let expr = Self::convert_condition_to_string(condition_tree);
+            Statement::If { condition_tree, if_body, elseif_branches, else_body } => {
+                let expr = self.convert_condition_to_string(condition_tree);
 
                 output.push("if ".to_owned() + &expr + " {");
+                output.append(&mut self.evaluate_block(if_body));
+
+                for (condition, body) in elseif_branches {
+                    let expr = self.convert_condition_to_string(condition);
 
-                let mut other_statements: Vec<String> = 
-                if_body
-                    .iter()
-                    .flat_map(|s| { Self::evaluate(s, used_variables) })
-                    .collect();
+                    output.push("} else if ".to_owned() + &expr + " {");
+                    output.append(&mut self.evaluate_block(body));
+                }
 
-                output.append(&mut other_statements);
+                if !else_body.is_empty() {
+                    output.push("} else {".to_string());
+                    output.append(&mut self.evaluate_block(else_body));
+                }
 
                 output.push("}".to_string());
             }
             Statement::While { condition_tree, while_body } => {
-                let expr = Self::convert_condition_to_string(condition_tree);
+                // rustc frowns upon "while true"
+                match condition_tree {
+                    Condition::Value { value: Token::Bool(true, _), .. } => output.push("loop {".to_string()),
+                    _ => output.push("while ".to_owned() + &self.convert_condition_to_string(condition_tree) + " {"),
+                }
+
+                output.append(&mut self.evaluate_loop_body(while_body, "break", "continue"));
+                output.push("}".to_string());
+            }
+            Statement::For { identifier, start, end, step, for_body, span } => {
+                let variable = Self::unwrap_value_token(identifier.clone());
 
-                output.push("while ".to_owned() + &expr + " {");
+                self.loop_depth += 1;
+                let depth = self.loop_depth;
 
-                let mut other_statements: Vec<String> = 
-                while_body
-                    .iter()
-                    .flat_map(|s| { Self::evaluate(s, used_variables) })
-                    .collect();
+                // start and end are worked out once, before the counter changes
+                output.push(format!("let primal_start_{}: i32 = {};", depth, self.convert_small_to_string(start)));
+                output.push(format!("let primal_end_{}: i32 = {};", depth, self.convert_small_to_string(end)));
+                output.push(format!("{} = {};", variable, self.convert_from_small(&format!("primal_start_{}", depth))));
 
-                output.append(&mut other_statements);
+                let constant_step = match step {
+                    None => Some(1),
+                    Some(Expression::Value { value: Token::Number(step, _), .. }) => Some(*step),
+                    Some(_) => None,
+                };
 
-                output.push("}".to_string());
+                match constant_step {
+                    // a plain Rust range, unless the body moves the counter itself
+                    Some(step) if step != 0 && !Self::assigns(for_body, &identifier.to_string()) => {
+                        let range = match step > 0 {
+                            true => format!("(primal_start_{0}..=primal_end_{0})", depth),
+                            false => format!("(primal_end_{0}..=primal_start_{0}).rev()", depth),
+                        };
+                        let step_by = match step.unsigned_abs() {
+                            1 => String::new(),
+                            step => format!(".step_by({})", step),
+                        };
+
+                        output.push(format!("for primal_value in {}{} {{", range, step_by));
+                        output.push(format!("{} = {};", variable, self.convert_from_small("primal_value")));
+                        output.append(&mut self.evaluate_loop_body(for_body, "break", "continue"));
+                        output.push("}".to_string());
+                    }
+                    _ => {
+                        let step = match step {
+                            Some(step) => self.convert_small_to_string(step),
+                            None => "1".to_string(),
+                        };
+                        // the body may set the counter to anything
+                        let counter = self.convert_small_to_string(&Expression::Value { value: identifier.clone(), span: start.span() });
+
+                        self.use_helper(FOR_HELPER);
+                        output.push(format!("let primal_step_{}: i32 = {};", depth, step));
+                        output.push(format!("if primal_in_range({0}, primal_step_{1}, primal_end_{1}, {2}) {{", counter, depth, span.line));
+
+                        // CONTINUE must not skip counting, so it leaves a labelled body instead
+                        if Self::continues(for_body) {
+                            output.push(format!("'primal_loop_{}: loop {{", depth));
+                            output.push(format!("'primal_body_{}: {{", depth));
+                            output.append(&mut self.evaluate_loop_body(
+                                for_body,
+                                &format!("break 'primal_loop_{}", depth),
+                                &format!("break 'primal_body_{}", depth),
+                            ));
+                            output.push("}".to_string());
+                        } else {
+                            output.push("loop {".to_string());
+                            output.append(&mut self.evaluate_loop_body(for_body, "break", "continue"));
+                        }
+
+                        // stop on the last value in range instead of going past the end
+                        output.push(format!("match {}.checked_add(primal_step_{}) {{", counter, depth));
+                        output.push(format!(
+                            "Some(primal_next) if primal_in_range(primal_next, primal_step_{0}, primal_end_{0}, {1}) => {2} = {3},",
+                            depth,
+                            span.line,
+                            variable,
+                            self.convert_from_small("primal_next")
+                        ));
+                        output.push("_ => break,".to_string());
+                        output.push("}".to_string());
+                        output.push("}".to_string());
+                        output.push("}".to_string());
+                    }
+                }
+
+                self.loop_depth -= 1;
             }
-            Statement::Input { message, identifier } => {
+            Statement::Input { message, identifier, span } => {
                 let text = Self::unwrap_value_token(message.clone());
                 let variable = Self::unwrap_value_token(identifier.clone());
 
-                if !used_variables.contains_key(&variable) {
-                    output.push("let mut ".to_owned() + &variable + " = Default::default();");
-                }
-                output.push("println!(\"{}\", ".to_owned() + &text + ");");
-                output.push("std::io::stdin().read_line(&mut ".to_owned() + &variable + ").expect(\"Failed to read user input\");");
-                output.push("let mut ".to_owned() + &variable + " = user_input.trim().parse().unwrap();");
+                let expected = match self.scope_symbols()[&identifier.to_string()] {
+                    Type::Number => "a whole number",
+                    Type::Float => "a number",
+                    Type::String => "some text",
+                    Type::Bool => "true or false",
+                };
+
+                self.use_helper(INPUT_HELPER);
+                output.push(format!("{} = primal_input({}, \"{}\", {});", variable, text, expected, span.line));
+            }
+            Statement::Break => output.push(self.loops.last().expect("BREAK outside of a loop").0.clone() + ";"),
+            Statement::Continue => output.push(self.loops.last().expect("CONTINUE outside of a loop").1.clone() + ";"),
+            Statement::Call { name, arguments, span } => output.push(self.convert_call_to_string(name, arguments, span.line) + ";"),
+            Statement::Return { value: Some(value), .. } => {
+                let target = self.scope.as_ref().and_then(|sub| self.symbols.subs[sub].returns);
+
+                output.push("return ".to_owned() + &self.convert_widened_to_string(value, target) + ";");
+            }
+            Statement::Return { value: None, .. } => output.push("return;".to_string()),
+            Statement::Goto { label, .. } => {
+                output.push(format!("primal_pc = {};", self.jump_targets[label]));
+                output.push("continue 'primal_dispatch;".to_string());
+            }
+            Statement::Gosub { label, .. } => {
+                output.push(format!("primal_returns.push({});", self.state + 1));
+                output.push(format!("primal_pc = {};", self.jump_targets[label]));
+                output.push("continue 'primal_dispatch;".to_string());
+            }
+            Statement::GosubReturn { span } => {
+                output.push("primal_pc = primal_returns.pop().unwrap_or_else(|| {".to_string());
+                output.push(format!(
+                    "eprintln!(\"Runtime error at line {}: `RETURN` without a `GOSUB` to go back to\");",
+                    span.line
+                ));
+                output.push("std::process::exit(1)".to_string());
+                output.push("});".to_string());
+                output.push("continue 'primal_dispatch;".to_string());
             }
-            Statement::Empty => {}
+            // written after main by emit, or where the states of main begin
+            Statement::Sub { .. } | Statement::Label { .. } | Statement::Empty => {}
         }
         output
     }
+
+    fn evaluate_block(&mut self, statements: &[Statement]) -> Vec<String> {
+        statements
+            .iter()
+            .flat_map(|statement| self.evaluate(statement))
+            .collect()
+    }
+
+    fn evaluate_loop_body(&mut self, statements: &[Statement], break_code: &str, continue_code: &str) -> Vec<String> {
+        self.loops.push((break_code.to_string(), continue_code.to_string()));
+        let output = self.evaluate_block(statements);
+        self.loops.pop();
+
+        output
+    }
+
+    // whether a CONTINUE belongs to this loop, and not to one nested in it
+    fn continues(statements: &[Statement]) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Continue => true,
+            Statement::If { if_body, elseif_branches, else_body, .. } => {
+                Self::continues(if_body)
+                    || elseif_branches.iter().any(|(_, body)| Self::continues(body))
+                    || Self::continues(else_body)
+            }
+            _ => false,
+        })
+    }
+
+    // whether any of the statements sets the variable
+    fn assigns(statements: &[Statement], variable: &str) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Let { identifier: Token::Identifier(name, _), .. }
+            | Statement::Input { identifier: Token::Identifier(name, _), .. } => name == variable,
+            Statement::For { identifier: Token::Identifier(name, _), for_body, .. } => {
+                name == variable || Self::assigns(for_body, variable)
+            }
+            Statement::If { if_body, elseif_branches, else_body, .. } => {
+                Self::assigns(if_body, variable)
+                    || elseif_branches.iter().any(|(_, body)| Self::assigns(body, variable))
+                    || Self::assigns(else_body, variable)
+            }
+            Statement::While { while_body, .. } => Self::assigns(while_body, variable),
+            _ => false,
+        })
+    }
+
+    fn use_helper(&mut self, helper: &'static str) {
+        if !self.helpers.contains(&helper) {
+            self.helpers.push(helper);
+        }
+    }
+
+    fn rust_type(&self, variable_type: Type) -> &'static str {
+        match variable_type {
+            Type::Number if self.symbols.numbers == NumberMode::Big => "PrimalInt",
+            Type::Number => "i32",
+            Type::Float => "f64",
+            Type::String => "String",
+            Type::Bool => "bool",
+        }
+    }
+
+    fn default_value(&self, variable_type: Type) -> &'static str {
+        match variable_type {
+            Type::Number if self.symbols.numbers == NumberMode::Big => "PrimalInt::default()",
+            Type::Number => "0",
+            Type::Float => "0.0",
+            Type::String => "String::new()",
+            Type::Bool => "false",
+        }
+    }
+
+    // owned Strings everywhere, so that assignments neither move nor borrow
+    fn convert_value_to_string(&self, value: &Token) -> String {
+        let is_big = self.symbols.numbers == NumberMode::Big;
+
+        match value {
+            // Debug writes the text as a Rust string literal, with anything special escaped
+            Token::String(text, _) => format!("String::from({:?})", text),
+            Token::Identifier(name, _) if self.scope_symbols().get(name) == Some(&Type::String) => Self::rust_name(name) + ".clone()",
+            Token::Identifier(name, _) if is_big && self.scope_symbols().get(name) == Some(&Type::Number) => Self::rust_name(name) + ".clone()",
+            Token::Identifier(name, _) => Self::rust_name(name),
+            Token::Number(value, _) if is_big => format!("PrimalInt::from({})", value),
+            Token::BigNumber(digits, _) => format!("\"{}\".parse::<PrimalInt>().unwrap()", digits),
+            _ => Lexer::convert_token_to_string(value.clone()),
+        }
+    }
     
-    fn convert_condition_to_string(condition: &Condition) -> String {
-        let tokens = condition.get_tokens_from();
+    fn convert_condition_to_string(&mut self, condition: &Condition) -> String {
+        match condition {
+            Condition::Value { value, .. } => self.convert_value_to_string(value),
+            Condition::Comparison { left, operator, right, .. } => {
+                let is_float = self.type_of(left) == Some(Type::Float) || self.type_of(right) == Some(Type::Float);
+                let target = is_float.then_some(Type::Float);
 
-        Self::convert_expr_to_string(tokens)
+                self.convert_widened_to_string(left, target)
+                    + " " + &Lexer::convert_token_to_string(operator.clone()) + " "
+                    + &self.convert_widened_to_string(right, target)
+            }
+            // "!" binds stronger than comparisons in Rust
+            Condition::Not { operand, .. } => {
+                let output = self.convert_condition_to_string(operand);
+
+                match **operand {
+                    Condition::Value { .. } | Condition::Not { .. } => "!".to_owned() + &output,
+                    _ => "!(".to_owned() + &output + ")",
+                }
+            }
+            Condition::Binary { left, operator, right, .. } => {
+                self.convert_condition_operand_to_string(left, condition.precedence())
+                    + " " + &Lexer::convert_token_to_string(operator.clone()) + " "
+                    + &self.convert_condition_operand_to_string(right, condition.precedence())
+            }
+        }
     }
 
-    fn convert_expression_to_string(expression: &Expression) -> String {
-        let tokens = expression.get_tokens_from();
+    fn convert_condition_operand_to_string(&mut self, operand: &Condition, precedence: u8) -> String {
+        let output = self.convert_condition_to_string(operand);
 
-        Self::convert_expr_to_string(tokens)
+        if operand.precedence() < precedence {
+            return "(".to_owned() + &output + ")";
+        }
+        output
     }
 
-    fn convert_expr_to_string(expression: Vec<Token>) -> String {
-        expression.iter().map(|token| Lexer::convert_token_to_string(token.clone())).collect::<Vec<String>>().join(" ")
+    fn convert_expression_to_string(&mut self, expression: &Expression) -> String {
+        match expression {
+            Expression::Value { value, .. } => self.convert_value_to_string(value),
+            // Rust has no unary plus
+            Expression::Unary { operator: Token::Plus, operand, .. } => {
+                self.convert_operand_to_string(operand, expression.precedence(), false)
+            }
+            // i32::MIN has no positive counterpart
+            Expression::Unary { operator: Token::Minus, operand, span } if self.is_checked(expression) => {
+                self.use_helper(CHECKED_HELPER);
+                format!("primal_checked(i32::checked_neg({}), {})", self.convert_expression_to_string(operand), span.line)
+            }
+            Expression::Unary { operator, operand, .. } => {
+                Lexer::convert_token_to_string(operator.clone())
+                    + &self.convert_operand_to_string(operand, expression.precedence(), true)
+            }
+            Expression::Binary { left, operator, right, span } if self.is_checked(expression) => {
+                self.convert_checked_to_string(left, operator, right, span.line)
+            }
+            // the String on the left is owned already, the one on the right only has to be borrowed
+            Expression::Binary { left, right, .. } if self.type_of(expression) == Some(Type::String) => {
+                let precedence = expression.precedence();
+
+                self.convert_operand_to_string(left, precedence, false)
+                    + " + &"
+                    + &self.convert_operand_to_string(right, precedence, true)
+            }
+            Expression::Binary { left, operator, right, .. } => {
+                let precedence = expression.precedence();
+                let is_float = self.type_of(expression) == Some(Type::Float);
+
+                self.convert_binary_operand_to_string(left, precedence, false, is_float)
+                    + " " + &Lexer::convert_token_to_string(operator.clone()) + " "
+                    + &self.convert_binary_operand_to_string(right, precedence, true, is_float)
+            }
+            Expression::Call { name, arguments, span } => self.convert_call_to_string(name, arguments, span.line),
+            Expression::Element { identifier, index, .. } => {
+                let element = self.convert_element_to_string(identifier, index);
+
+                match self.scope_arrays().get(&identifier.to_string()) {
+                    Some(Type::String) => element + ".clone()",
+                    Some(Type::Number) if self.symbols.numbers == NumberMode::Big => element + ".clone()",
+                    _ => element,
+                }
+            }
+        }
+    }
+
+    // Number arithmetic which can fail, f64 never does and PrimalInt only when dividing by zero
+    fn is_checked(&self, expression: &Expression) -> bool {
+        if self.type_of(expression) != Some(Type::Number) {
+            return false;
+        }
+
+        match (self.symbols.numbers, expression) {
+            (NumberMode::Fixed, _) => true,
+            (NumberMode::Big, Expression::Binary { operator, .. }) => matches!(operator, Token::Divide | Token::Modulo),
+            (NumberMode::Big, _) => false,
+        }
+    }
+
+    // the helpers are functions, so the operands need no parentheses;
+    // i32::checked_add(1, 2) because 1.checked_add(2) does not compile
+    fn convert_checked_to_string(&mut self, left: &Expression, operator: &Token, right: &Expression, line: usize) -> String {
+        let is_big = self.symbols.numbers == NumberMode::Big;
+        let left = self.convert_expression_to_string(left);
+        let right = self.convert_expression_to_string(right);
+
+        let checked = match operator {
+            Token::Plus => "i32::checked_add",
+            Token::Minus => "i32::checked_sub",
+            Token::Times => "i32::checked_mul",
+            Token::Divide | Token::Modulo => {
+                let (helper, function) = match (operator, is_big) {
+                    (Token::Divide, true) => (BIG_DIVIDE_HELPER, "primal_divide"),
+                    (_, true) => (BIG_REMAINDER_HELPER, "primal_remainder"),
+                    (Token::Divide, false) => (DIVIDE_HELPER, "primal_divide"),
+                    (_, false) => (REMAINDER_HELPER, "primal_remainder"),
+                };
+
+                self.use_helper(helper);
+                if !is_big {
+                    self.use_helper(CHECKED_HELPER);
+                }
+                return format!("{}({}, {}, {})", function, left, right, line);
+            }
+            _ => unreachable!("not an arithmetic operator: {:?}", operator),
+        };
+
+        self.use_helper(CHECKED_HELPER);
+        format!("primal_checked({}({}, {}), {})", checked, left, right, line)
+    }
+
+    // one print! for the whole line, every item an argument of its own; the items up to a ","
+    // go through primal_zone together, they start at the beginning of a tab zone
+    fn convert_print_to_string(&mut self, items: &[(Expression, Option<Separator>)]) -> String {
+        let mut format = String::new();
+        let mut arguments: Vec<String> = vec![];
+        let mut zone: Vec<String> = vec![];
+
+        for (expression, separator) in items {
+            zone.push(match expression {
+                // a literal can be printed as it is, even one too big for Rust
+                Expression::Value { value: Token::String(text, _), .. } => format!("{:?}", text),
+                Expression::Value { value: Token::BigNumber(digits, _), .. } => format!("\"{}\"", digits),
+                expression => self.convert_expression_to_string(expression),
+            });
+
+            if *separator == Some(Separator::Zone) {
+                let text = match zone.len() {
+                    1 => zone.remove(0),
+                    length => format!("format_args!(\"{}\", {})", "{}".repeat(length), zone.join(", ")),
+                };
+
+                self.use_helper(ZONE_HELPER);
+                zone.clear();
+                format.push_str("{}");
+                arguments.push(format!("primal_zone({})", text));
+            }
+        }
+
+        format.push_str(&"{}".repeat(zone.len()));
+        arguments.append(&mut zone);
+
+        let print = match items.last() {
+            Some((_, Some(_))) => "print!",
+            _ => "println!",
+        };
+
+        match arguments.is_empty() {
+            true => format!("{}();", print),
+            false => format!("{}(\"{}\", {});", print, format, arguments.join(", ")),
+        }
+    }
+
+    // Rust never mixes i32 and f64, so a Number going where a Float is expected gets converted
+    fn convert_widened_to_string(&mut self, expression: &Expression, target: Option<Type>) -> String {
+        // a literal can be written as a Float right away
+        match (target, expression) {
+            (Some(Type::Float), Expression::Value { value: Token::Number(value, _), .. }) => {
+                return format!("{:?}", f64::from(*value));
+            }
+            (Some(Type::Float), Expression::Value { value: Token::BigNumber(digits, _), .. }) => {
+                return format!("{:?}", digits.parse::<f64>().unwrap_or(f64::NAN));
+            }
+            _ => {}
+        }
+
+        let output = self.convert_expression_to_string(expression);
+
+        match (target, self.type_of(expression)) {
+            (Some(Type::Float), Some(Type::Number)) => "f64::from(".to_owned() + &output + ")",
+            _ => output,
+        }
+    }
+
+    fn convert_binary_operand_to_string(&mut self, operand: &Expression, precedence: u8, is_right: bool, is_float: bool) -> String {
+        if is_float && self.type_of(operand) == Some(Type::Number) {
+            return self.convert_widened_to_string(operand, Some(Type::Float));
+        }
+        self.convert_operand_to_string(operand, precedence, is_right)
+    }
+
+    // i32 for FOR loops, DIM and indices, whatever size Numbers are
+    fn convert_small_to_string(&mut self, expression: &Expression) -> String {
+        let output = self.convert_expression_to_string(expression);
+
+        match self.symbols.numbers {
+            NumberMode::Fixed => output,
+            NumberMode::Big => {
+                self.use_helper(SMALL_HELPER);
+                format!("primal_small({}, {})", output, expression.span().line)
+            }
+        }
+    }
+
+    // the other way round, an i32 for a Number variable
+    fn convert_from_small(&self, value: &str) -> String {
+        match self.symbols.numbers {
+            NumberMode::Fixed => value.to_string(),
+            NumberMode::Big => format!("PrimalInt::from({})", value),
+        }
+    }
+
+    fn convert_element_to_string(&mut self, identifier: &Token, index: &Expression) -> String {
+        Self::unwrap_value_token(identifier.clone()) + "[" + &self.convert_position_to_string(identifier, index) + "]"
+    }
+
+    // checked by primal_index, a plain Rust index would panic
+    fn convert_position_to_string(&mut self, identifier: &Token, index: &Expression) -> String {
+        self.use_helper(INDEX_HELPER);
+        format!(
+            "primal_index({}, {}.len(), \"{}\", {})",
+            self.convert_small_to_string(index),
+            Self::unwrap_value_token(identifier.clone()),
+            identifier,
+            index.span().line
+        )
+    }
+
+    // counts and positions are i32 whatever size Numbers are
+    fn convert_builtin_to_string(&mut self, builtin: Builtin, arguments: &[Expression], line: usize) -> String {
+        let is_big = self.symbols.numbers == NumberMode::Big;
+        let text = arguments.first().map(|text| self.convert_expression_to_string(text)).unwrap_or_default();
+        let numbers: Vec<String> = arguments.iter().skip(1).map(|number| self.convert_small_to_string(number)).collect();
+
+        match builtin {
+            Builtin::Len => {
+                self.use_helper(LEN_HELPER);
+                self.convert_from_small(&format!("primal_len({})", text))
+            }
+            Builtin::Mid => {
+                let length = match numbers.get(1) {
+                    Some(length) => format!("Some({})", length),
+                    None => "None".to_string(),
+                };
+
+                self.use_helper(MID_HELPER);
+                format!("primal_mid({}, {}, {}, {})", text, numbers[0], length, line)
+            }
+            Builtin::Left => {
+                self.use_helper(LEFT_HELPER);
+                format!("primal_left({}, {}, {})", text, numbers[0], line)
+            }
+            Builtin::Right => {
+                self.use_helper(RIGHT_HELPER);
+                format!("primal_right({}, {}, {})", text, numbers[0], line)
+            }
+            Builtin::Str => {
+                self.use_helper(STR_HELPER);
+                format!("primal_str({})", text)
+            }
+            Builtin::Val if is_big => {
+                self.use_helper(BIG_VAL_HELPER);
+                format!("primal_val({})", text)
+            }
+            Builtin::Val => {
+                self.use_helper(VAL_HELPER);
+                format!("primal_val({}, {})", text, line)
+            }
+        }
+    }
+
+    fn convert_call_to_string(&mut self, name: &Token, arguments: &[Expression], line: usize) -> String {
+        if let Some(builtin) = Builtin::find(&name.to_string()) {
+            return self.convert_builtin_to_string(builtin, arguments, line);
+        }
+
+        let parameters = self.symbols.subs
+            .get(&name.to_string())
+            .map(|sub| sub.parameters.clone())
+            .unwrap_or_default();
+        let arguments: Vec<String> = arguments
+            .iter()
+            .enumerate()
+            .map(|(position, argument)| self.convert_widened_to_string(argument, parameters.get(position).copied()))
+            .collect();
+
+        Self::unwrap_value_token(name.clone()) + "(" + &arguments.join(", ") + ")"
+    }
+
+    // parenthesise operands which would otherwise bind differently, e.g. "a - (b - c)" or "-(-a)"
+    fn convert_operand_to_string(&mut self, operand: &Expression, precedence: u8, is_right: bool) -> String {
+        if let Expression::Unary { operator: Token::Plus, operand, .. } = operand {
+            return self.convert_operand_to_string(operand, precedence, is_right);
+        }
+
+        let output = self.convert_expression_to_string(operand);
+        let is_nested_unary = matches!(operand, Expression::Unary { .. }) && is_right;
+
+        if operand.precedence() < precedence
+        || (is_right && operand.precedence() == precedence && !matches!(operand, Expression::Value { .. }))
+        || is_nested_unary {
+            return "(".to_owned() + &output + ")";
+        }
+        output
+    }
+
+    // a primal name as a Rust identifier, `fn` becomes `r#fn` and `self`, which cannot be raw, `primal_self`
+    fn rust_name(name: &str) -> String {
+        match name {
+            name if UNRAW_KEYWORDS.contains(&name) || PRELUDE_VARIANTS.contains(&name) => format!("{}{}", RESERVED_PREFIX, name),
+            name if RUST_KEYWORDS.contains(&name) => format!("r#{}", name),
+            name => name.to_string(),
+        }
     }
 
     fn unwrap_value_token(token: Token) -> String {
@@ -117,16 +1090,20 @@ impl Emitter {
             return value.to_string();
         }
 
-        if let Token::String(value, _) = token {
-            return value;
+        if let Token::String(text, _) = token {
+            return format!("{:?}", text);
         }
 
         if let Token::Number(value, _) = token {
             return value.to_string();
         }
 
-        if let Token::Identifier(value, _) = token {
-            return value.to_string();
+        if let Token::Float(..) = token {
+            return Lexer::convert_token_to_string(token);
+        }
+
+        if let Token::Identifier(name, _) = token {
+            return Self::rust_name(&name);
         }
         
         panic!("Compile error: wrong value for token");
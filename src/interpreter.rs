@@ -0,0 +1,1073 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use crate::bigint::PrimalInt;
+use crate::builtins::Builtin;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::Token;
+use crate::parser::{Condition, Expression, Separator, Statement, ZONE_WIDTH};
+use crate::semantic::{NumberMode, SymbolTable, Symbols, Type};
+use crate::strings;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(i32),
+    // only with `--bigint`, and only for Numbers which do not fit in an i32
+    BigNumber(PrimalInt),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// Runs the Abstract Syntax Tree directly, no Rust toolchain needed.
+pub struct Interpreter<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    symbols: Symbols,
+    subs: HashMap<String, Rc<Sub>>,
+    // variables of the running SUB, or of the main program
+    variables: HashMap<String, Value>,
+    scope: Option<String>,
+    call_depth: usize,
+}
+
+type RunResult<T> = Result<T, Diagnostic>;
+
+// where to go after a statement
+#[derive(Debug, Clone, PartialEq)]
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Option<Value>),
+    // to a line number of the main program
+    Goto(i32),
+    // with where RETURN comes back to, the innermost block first
+    Gosub(i32, Vec<usize>),
+    GosubReturn(Span),
+}
+
+struct Sub {
+    parameters: Vec<String>,
+    body: Vec<Statement>,
+}
+
+// deep enough for any sane recursion
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// Stack the interpreter needs to reach `MAX_CALL_DEPTH`, run it on a thread this big.
+pub const STACK_SIZE: usize = MAX_CALL_DEPTH * 64 * 1024;
+
+impl<R: BufRead, W: Write> Interpreter<R, W> {
+    pub fn new(symbols: Symbols, input: R, output: W) -> Interpreter<R, W> {
+        Interpreter {
+            input,
+            output,
+            symbols,
+            subs: HashMap::new(),
+            variables: HashMap::new(),
+            scope: None,
+            call_depth: 0,
+        }
+    }
+
+    pub fn run(&mut self, statements: &[Statement]) -> RunResult<()> {
+        for statement in statements {
+            if let Statement::Sub { name: Token::Identifier(name, _), parameters, sub_body } = statement {
+                let sub = Sub {
+                    parameters: parameters.iter().map(|parameter| parameter.to_string()).collect(),
+                    body: sub_body.clone(),
+                };
+                self.subs.insert(name.to_string(), Rc::new(sub));
+            }
+        }
+
+        let labels: HashMap<i32, usize> = statements
+            .iter()
+            .enumerate()
+            .filter_map(|(index, statement)| match statement {
+                Statement::Label { number } => Some((*number, index)),
+                _ => None,
+            })
+            .collect();
+        // where each RETURN outside of SUBs goes back to
+        let mut gosubs: Vec<Vec<usize>> = vec![];
+        let mut counter = 0;
+        // the rest of a GOSUB inside an IF, for after its RETURN
+        let mut resume_at = None;
+
+        // the parser keeps BREAK and CONTINUE inside loops and RETURN with a value inside SUBs,
+        // and every jump lands on a line number of the main program
+        while let Some(statement) = statements.get(counter) {
+            let flow = match resume_at.take() {
+                Some(point) => self.resume(statement, point)?,
+                None => self.execute(statement)?,
+            };
+
+            counter = match flow {
+                Flow::Goto(label) => labels[&label],
+                Flow::Gosub(label, mut point) => {
+                    point.push(counter);
+                    gosubs.push(point);
+                    labels[&label]
+                }
+                Flow::GosubReturn(span) => {
+                    let mut point = gosubs
+                        .pop()
+                        .ok_or_else(|| Diagnostic::error("`RETURN` without a `GOSUB` to go back to".to_string(), span))?;
+                    let index = point.pop().expect("a GOSUB is somewhere in the main program");
+
+                    if point.is_empty() {
+                        index + 1
+                    } else {
+                        resume_at = Some(point);
+                        index
+                    }
+                }
+                _ => counter + 1,
+            };
+        }
+        self.output.flush().expect("Should have been able to flush the output");
+        Ok(())
+    }
+
+    fn execute(&mut self, statement: &Statement) -> RunResult<Flow> {
+        match statement {
+            Statement::Print { items } => {
+                // tab zones are counted from where the PRINT starts
+                let mut text = String::new();
+
+                for (expression, separator) in items {
+                    text += &self.evaluate(expression)?.to_string();
+
+                    if *separator == Some(Separator::Zone) {
+                        let width = text.chars().count();
+                        text += &" ".repeat(ZONE_WIDTH - width % ZONE_WIDTH);
+                    }
+                }
+
+                // a separator at the end keeps the line going
+                if !matches!(items.last(), Some((_, Some(_)))) {
+                    text.push('\n');
+                }
+
+                write!(self.output, "{}", text).expect("Should have been able to write the output");
+            }
+            Statement::Let { identifier, expression_tree } => {
+                let value = self.evaluate(expression_tree)?;
+                let target = self.scope_symbols().get(&identifier.to_string()).copied();
+
+                self.assign(identifier, Self::widen(value, target));
+            }
+            Statement::Dim { identifier: identifier @ Token::Identifier(name, _), size } => {
+                let last = self.evaluate_number(size)?;
+
+                if last < 0 {
+                    return Err(Diagnostic::error(
+                        format!("the last index of an array must not be negative, found {}", last),
+                        size.span(),
+                    ));
+                }
+
+                let element = Self::default_value(self.scope_arrays()[name]);
+                self.assign(identifier, Value::Array(vec![element; last as usize + 1]));
+            }
+            Statement::LetElement { identifier: Token::Identifier(name, _), index, expression_tree } => {
+                // like Rust, the value first and then the place it goes to
+                let value = self.evaluate(expression_tree)?;
+                let value = Self::widen(value, self.scope_arrays().get(name).copied());
+                let position = self.evaluate_number(index)?;
+                let position = self.element_position(name, position, index.span())?;
+
+                if let Some(Value::Array(elements)) = self.variables.get_mut(name) {
+                    elements[position] = value;
+                }
+            }
+            Statement::Dim { .. } | Statement::LetElement { .. } => {}
+            Statement::If { condition_tree, elseif_branches, .. } => {
+                // the ELSE comes after the IF and every ELSEIF
+                let mut branch = elseif_branches.len() + 1;
+
+                if self.check(condition_tree)? {
+                    branch = 0;
+                } else {
+                    for (index, (condition, _)) in elseif_branches.iter().enumerate() {
+                        if self.check(condition)? {
+                            branch = index + 1;
+                            break;
+                        }
+                    }
+                }
+
+                let flow = self.run_block(Self::branch(statement, branch), 0)?;
+                return Ok(Self::came_from(flow, branch));
+            }
+            Statement::While { condition_tree, while_body } => {
+                while self.check(condition_tree)? {
+                    match self.run_block(while_body, 0)? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Statement::For { identifier: identifier @ Token::Identifier(name, _), start, end, step, for_body, .. } => {
+                let start = self.evaluate_number(start)?;
+                let end = self.evaluate_number(end)?;
+                let step = match step {
+                    Some(step) => match self.evaluate_number(step)? {
+                        0 => return Err(Diagnostic::error("the STEP of a FOR loop must not be 0".to_string(), step.span())),
+                        value => value,
+                    },
+                    None => 1,
+                };
+
+                // the counter stops at the last value in range, it never goes past the end
+                let is_in_range = |value: i32| if step > 0 { value <= end } else { value >= end };
+
+                self.assign(identifier, Value::Number(start));
+
+                if is_in_range(start) {
+                    loop {
+                        match self.run_block(for_body, 0)? {
+                            Flow::Break => break,
+                            Flow::Next | Flow::Continue => {}
+                            flow => return Ok(flow),
+                        }
+
+                        // the body may have moved the counter too
+                        let next = match self.variables.get(name) {
+                            Some(Value::Number(value)) => value.checked_add(step).filter(|next| is_in_range(*next)),
+                            _ => None,
+                        };
+
+                        match next {
+                            Some(next) => self.assign(identifier, Value::Number(next)),
+                            None => break,
+                        }
+                    }
+                }
+            }
+            Statement::For { .. } => {}
+            Statement::Input { message, identifier: identifier @ Token::Identifier(name, _), span } => {
+                let expected = self.scope_symbols()[name];
+
+                // ask until the answer fits the type of the variable
+                let value = loop {
+                    writeln!(self.output, "{}", Self::literal(message)).expect("Should have been able to write the output");
+                    self.output.flush().expect("Should have been able to flush the output");
+
+                    let mut line = String::new();
+                    let length = self.input.read_line(&mut line).expect("Should have been able to read user input");
+
+                    if length == 0 {
+                        return Err(Diagnostic::error(
+                            format!("expected {} for `{}`, but the input has ended", Self::describe_input(expected), name),
+                            *span,
+                        ));
+                    }
+
+                    match self.parse_input(line.trim(), expected) {
+                        Some(value) => break value,
+                        None => writeln!(self.output, "Please enter {}.", Self::describe_input(expected))
+                            .expect("Should have been able to write the output"),
+                    }
+                };
+
+                self.assign(identifier, value);
+            }
+            Statement::Input { .. } => {}
+            Statement::Break => return Ok(Flow::Break),
+            Statement::Continue => return Ok(Flow::Continue),
+            Statement::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => Some(self.evaluate(value)?),
+                    None => None,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Statement::Call { name, arguments, span } => {
+                self.call(name, arguments, *span)?;
+            }
+            Statement::Goto { label, .. } => return Ok(Flow::Goto(*label)),
+            Statement::Gosub { label, .. } => return Ok(Flow::Gosub(*label, vec![])),
+            Statement::GosubReturn { span } => return Ok(Flow::GosubReturn(*span)),
+            // collected before the run, a SUB only runs when called
+            Statement::Sub { .. } | Statement::Label { .. } | Statement::Empty => {}
+        }
+        Ok(Flow::Next)
+    }
+
+    // stops early at BREAK, CONTINUE, RETURN or a jump and hands it over to the loop, the call or run
+    fn run_block(&mut self, statements: &[Statement], start: usize) -> RunResult<Flow> {
+        for (index, statement) in statements.iter().enumerate().skip(start) {
+            let flow = self.execute(statement)?;
+
+            if flow != Flow::Next {
+                return Ok(Self::came_from(flow, index));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    // goes on right after the GOSUB which `point` leads to inside of an IF
+    fn resume(&mut self, statement: &Statement, mut point: Vec<usize>) -> RunResult<Flow> {
+        let branch = point.pop().expect("a GOSUB is inside a branch");
+        let index = point.pop().expect("a GOSUB is inside a branch");
+        let body = Self::branch(statement, branch);
+
+        let flow = match point.is_empty() {
+            true => Flow::Next,
+            false => Self::came_from(self.resume(&body[index], point)?, index),
+        };
+        let flow = match flow {
+            Flow::Next => self.run_block(body, index + 1)?,
+            flow => flow,
+        };
+
+        Ok(Self::came_from(flow, branch))
+    }
+
+    // the statements of an IF which run for its `branch`th condition, or its ELSE
+    fn branch(statement: &Statement, branch: usize) -> &[Statement] {
+        match statement {
+            Statement::If { if_body, .. } if branch == 0 => if_body,
+            Statement::If { elseif_branches, .. } if branch <= elseif_branches.len() => &elseif_branches[branch - 1].1,
+            Statement::If { else_body, .. } => else_body,
+            _ => unreachable!("RETURN only comes back into IFs"),
+        }
+    }
+
+    // a GOSUB keeps track of the blocks it is in, for RETURN to find its way back
+    fn came_from(flow: Flow, index: usize) -> Flow {
+        match flow {
+            Flow::Gosub(label, mut point) => {
+                point.push(index);
+                Flow::Gosub(label, point)
+            }
+            flow => flow,
+        }
+    }
+
+    // what the SUB returned, if anything
+    fn call(&mut self, name: &Token, arguments: &[Expression], span: Span) -> RunResult<Option<Value>> {
+        let name = name.to_string();
+
+        if let Some(builtin) = Builtin::find(&name) {
+            return self.call_builtin(builtin, arguments, span).map(Some);
+        }
+
+        let sub = self.subs
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| Diagnostic::error(format!("unknown SUB `{}`", name), span))?;
+
+        if self.call_depth == MAX_CALL_DEPTH {
+            return Err(Diagnostic::error(
+                format!("too many nested calls, does `{}` call itself forever?", name),
+                span,
+            ));
+        }
+
+        let signature = self.symbols.subs.get(&name).cloned();
+        let parameter_types = signature.as_ref().map(|sub| sub.parameters.clone()).unwrap_or_default();
+        let returns = signature.and_then(|sub| sub.returns);
+
+        // the arguments belong to the caller's scope
+        let mut variables = HashMap::new();
+        for (position, (parameter, argument)) in sub.parameters.iter().zip(arguments).enumerate() {
+            let value = self.evaluate(argument)?;
+            variables.insert(parameter.to_string(), Self::widen(value, parameter_types.get(position).copied()));
+        }
+
+        let outer_variables = std::mem::replace(&mut self.variables, variables);
+        let outer_scope = self.scope.replace(name);
+        self.call_depth += 1;
+
+        let flow = self.run_block(&sub.body, 0);
+
+        self.call_depth -= 1;
+        self.scope = outer_scope;
+        self.variables = outer_variables;
+
+        match flow? {
+            Flow::Return(value) => Ok(value.map(|value| Self::widen(value, returns))),
+            _ => Ok(None),
+        }
+    }
+
+    // the Analyzer has checked the number and the types of the arguments
+    fn call_builtin(&mut self, builtin: Builtin, arguments: &[Expression], span: Span) -> RunResult<Value> {
+        let values = arguments
+            .iter()
+            .map(|argument| self.evaluate(argument))
+            .collect::<RunResult<Vec<Value>>>()?;
+
+        let text = match values.first() {
+            Some(Value::String(text)) => text.as_str(),
+            _ => "",
+        };
+        let number = |position: usize| match values.get(position) {
+            Some(Value::Number(number)) => Ok(Some(*number)),
+            Some(value) => Err(Diagnostic::error(
+                format!("{} is too big for `{}`, it counts characters in 32 bits", value, builtin.name()),
+                arguments[position].span(),
+            )),
+            None => Ok(None),
+        };
+
+        let result = match builtin {
+            Builtin::Len => Ok(Value::Number(strings::len(text))),
+            Builtin::Mid => strings::mid(text, number(1)?.unwrap_or(1), number(2)?).map(Value::String),
+            Builtin::Left => strings::left(text, number(1)?.unwrap_or(0)).map(Value::String),
+            Builtin::Right => strings::right(text, number(1)?.unwrap_or(0)).map(Value::String),
+            Builtin::Str => Ok(Value::String(values[0].to_string())),
+            // with `--bigint` any number of digits fits
+            Builtin::Val if self.symbols.numbers == NumberMode::Big => Ok(match strings::leading_number(text) {
+                Some(digits) => Self::big_number(digits.parse().expect("leading_number should only give digits")),
+                None => Value::Number(0),
+            }),
+            Builtin::Val => strings::val(text).map(Value::Number),
+        };
+
+        result.map_err(|message| Diagnostic::error(message, span))
+    }
+
+    fn scope_symbols(&self) -> &SymbolTable {
+        self.symbols.variables_in(self.scope.as_deref())
+    }
+
+    fn scope_arrays(&self) -> &SymbolTable {
+        self.symbols.arrays_in(self.scope.as_deref())
+    }
+
+    // a Number kept where a Float is expected becomes one
+    fn widen(value: Value, target: Option<Type>) -> Value {
+        match (value, target) {
+            (Value::Number(number), Some(Type::Float)) => Value::Float(f64::from(number)),
+            (Value::BigNumber(number), Some(Type::Float)) => Value::Float(f64::from(number)),
+            (value, _) => value,
+        }
+    }
+
+    // an array which was never set up with DIM has no elements
+    fn element_position(&self, name: &str, index: i32, span: Span) -> RunResult<usize> {
+        let length = match self.variables.get(name) {
+            Some(Value::Array(elements)) => elements.len(),
+            _ => 0,
+        };
+
+        usize::try_from(index)
+            .ok()
+            .filter(|position| *position < length)
+            .ok_or_else(|| Diagnostic::error(
+                format!("index {} is out of bounds for `{}`, which has {} elements", index, name, length),
+                span,
+            ))
+    }
+
+    fn assign(&mut self, identifier: &Token, value: Value) {
+        if let Token::Identifier(name, _) = identifier {
+            self.variables.insert(name.to_string(), value);
+        }
+    }
+
+    fn evaluate(&mut self, expression: &Expression) -> RunResult<Value> {
+        match expression {
+            // like in the generated code, a variable which was not set yet holds the default value of its type
+            Expression::Value { value: Token::Identifier(name, _), .. } => Ok(self.variables
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| Self::default_value(self.scope_symbols()[name]))),
+            Expression::Value { value, .. } => Ok(Self::literal(value)),
+            Expression::Unary { operator, operand, span } => {
+                match (operator, self.evaluate(operand)?) {
+                    (Token::Plus, Value::Number(number)) => Ok(Value::Number(number)),
+                    (Token::Plus, Value::Float(number)) => Ok(Value::Float(number)),
+                    (Token::Minus, Value::Float(number)) => Ok(Value::Float(-number)),
+                    (Token::Plus, Value::BigNumber(number)) => Ok(Value::BigNumber(number)),
+                    (Token::Minus, Value::BigNumber(number)) => Ok(Self::big_number(-number)),
+                    (Token::Minus, Value::Number(number)) => match number.checked_neg() {
+                        Some(negated) => Ok(Value::Number(negated)),
+                        None if self.symbols.numbers == NumberMode::Big => Ok(Self::big_number(-PrimalInt::from(number))),
+                        None => Err(Diagnostic::error("integer overflow".to_string(), *span)),
+                    },
+                    (operator, value) => Err(Diagnostic::error(
+                        format!("cannot apply `{}` to {}", operator, value.type_name()),
+                        *span,
+                    )),
+                }
+            }
+            Expression::Binary { left, operator, right, span } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+
+                self.calculate(left, operator, right, *span)
+            }
+            Expression::Call { name, arguments, span } => {
+                if let Some(value) = self.call(name, arguments, *span)? {
+                    return Ok(value);
+                }
+
+                // the SUB ended without a RETURN
+                match self.symbols.subs.get(&name.to_string()).and_then(|sub| sub.returns) {
+                    Some(returns) => Ok(Self::default_value(returns)),
+                    None => Err(Diagnostic::error(format!("`{}` does not return a value", name), *span)),
+                }
+            }
+            Expression::Element { identifier, index, .. } => {
+                let name = identifier.to_string();
+                let position = self.evaluate_number(index)?;
+                let position = self.element_position(&name, position, index.span())?;
+
+                match self.variables.get(&name) {
+                    Some(Value::Array(elements)) => Ok(elements[position].clone()),
+                    _ => unreachable!("element_position checks for the array"),
+                }
+            }
+        }
+    }
+
+    fn evaluate_number(&mut self, expression: &Expression) -> RunResult<i32> {
+        match self.evaluate(expression)? {
+            Value::Number(value) => Ok(value),
+            Value::BigNumber(value) => Err(Diagnostic::error(
+                format!("{} is too big, FOR loops, array sizes and indices have to fit in 32 bits", value),
+                expression.span(),
+            )),
+            value => Err(Diagnostic::error(
+                format!("expected a Number, found {}", value.type_name()),
+                expression.span(),
+            )),
+        }
+    }
+
+    fn calculate(&self, left: Value, operator: &Token, right: Value, span: Span) -> RunResult<Value> {
+        if let (Value::String(left), Token::Plus, Value::String(right)) = (&left, operator, &right) {
+            return Ok(Value::String(left.to_owned() + right));
+        }
+
+        // like f64 in Rust, no errors, dividing by zero gives infinity or NaN
+        if let Some((left, right)) = Self::promote(&left, &right) {
+            return Ok(Value::Float(match operator {
+                Token::Plus => left + right,
+                Token::Minus => left - right,
+                Token::Times => left * right,
+                Token::Divide => left / right,
+                Token::Modulo => left % right,
+                _ => unreachable!("not an arithmetic operator: {:?}", operator),
+            }));
+        }
+
+        let is_division = matches!(operator, Token::Divide | Token::Modulo);
+
+        // i32 first, it is much cheaper
+        if let (Value::Number(left), Value::Number(right)) = (&left, &right) {
+            if is_division && *right == 0 {
+                return Err(Diagnostic::error("division by zero".to_string(), span));
+            }
+
+            let result = match operator {
+                Token::Plus => left.checked_add(*right),
+                Token::Minus => left.checked_sub(*right),
+                Token::Times => left.checked_mul(*right),
+                Token::Divide => left.checked_div(*right),
+                Token::Modulo => left.checked_rem(*right),
+                _ => unreachable!("not an arithmetic operator: {:?}", operator),
+            };
+
+            match result {
+                Some(result) => return Ok(Value::Number(result)),
+                // with `--bigint` the Number just gets bigger
+                None if self.symbols.numbers == NumberMode::Big => {}
+                None => return Err(Diagnostic::error("integer overflow".to_string(), span)),
+            }
+        }
+
+        let (Some(left), Some(right)) = (left.as_big_number(), right.as_big_number()) else {
+            return Err(Diagnostic::error(
+                format!("cannot apply `{}` to {} and {}", operator, left.type_name(), right.type_name()),
+                span,
+            ));
+        };
+
+        if is_division && right.is_zero() {
+            return Err(Diagnostic::error("division by zero".to_string(), span));
+        }
+
+        Ok(Self::big_number(match operator {
+            Token::Plus => left + right,
+            Token::Minus => left - right,
+            Token::Times => left * right,
+            Token::Divide => left / right,
+            Token::Modulo => left % right,
+            _ => unreachable!("not an arithmetic operator: {:?}", operator),
+        }))
+    }
+
+    // a Number which fits stays an i32, whatever it was worked out with
+    fn big_number(value: PrimalInt) -> Value {
+        match i32::try_from(&value) {
+            Ok(value) => Value::Number(value),
+            Err(_) => Value::BigNumber(value),
+        }
+    }
+
+    fn check(&mut self, condition: &Condition) -> RunResult<bool> {
+        match condition {
+            Condition::Value { value, span } => {
+                let expression = Expression::Value { value: value.clone(), span: *span };
+
+                match self.evaluate(&expression)? {
+                    Value::Bool(value) => Ok(value),
+                    value => Err(Diagnostic::error(format!("expected a Bool, found {}", value.type_name()), *span)),
+                }
+            }
+            Condition::Comparison { left, operator, right, span } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+
+                Self::compare(left, operator, right, *span)
+            }
+            Condition::Not { operand, .. } => Ok(!self.check(operand)?),
+            Condition::Binary { left, operator: Token::And, right, .. } => {
+                Ok(self.check(left)? && self.check(right)?)
+            }
+            Condition::Binary { left, right, .. } => Ok(self.check(left)? || self.check(right)?),
+        }
+    }
+
+    fn compare(left: Value, operator: &Token, right: Value, span: Span) -> RunResult<bool> {
+        // NaN has no ordering, so the operators themselves decide
+        if let Some((left, right)) = Self::promote(&left, &right) {
+            return Ok(match operator {
+                Token::Equals => left == right,
+                Token::NotEquals => left != right,
+                Token::MoreThan => left > right,
+                Token::MoreThanEquals => left >= right,
+                Token::LessThan => left < right,
+                Token::LessThanEquals => left <= right,
+                _ => unreachable!("not a comparison operator: {:?}", operator),
+            });
+        }
+
+        let ordering = match (&left, &right) {
+            (Value::Number(left), Value::Number(right)) => left.cmp(right),
+            (Value::Number(_) | Value::BigNumber(_), Value::Number(_) | Value::BigNumber(_)) => {
+                left.as_big_number().cmp(&right.as_big_number())
+            }
+            (Value::String(left), Value::String(right)) => left.cmp(right),
+            (Value::Bool(left), Value::Bool(right)) => left.cmp(right),
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("cannot compare {} with {}", left.type_name(), right.type_name()),
+                    span,
+                ));
+            }
+        };
+
+        Ok(match operator {
+            Token::Equals => ordering.is_eq(),
+            Token::NotEquals => ordering.is_ne(),
+            Token::MoreThan => ordering.is_gt(),
+            Token::MoreThanEquals => ordering.is_ge(),
+            Token::LessThan => ordering.is_lt(),
+            Token::LessThanEquals => ordering.is_le(),
+            _ => unreachable!("not a comparison operator: {:?}", operator),
+        })
+    }
+
+    // both sides as Floats, when at least one of them is a Float and the other one a Number
+    fn promote(left: &Value, right: &Value) -> Option<(f64, f64)> {
+        match (left, right) {
+            (Value::Float(left), Value::Float(right)) => Some((*left, *right)),
+            (Value::Float(left), Value::Number(right)) => Some((*left, f64::from(*right))),
+            (Value::Number(left), Value::Float(right)) => Some((f64::from(*left), *right)),
+            (Value::Float(left), Value::BigNumber(right)) => Some((*left, f64::from(right.clone()))),
+            (Value::BigNumber(left), Value::Float(right)) => Some((f64::from(left.clone()), *right)),
+            _ => None,
+        }
+    }
+
+    fn literal(token: &Token) -> Value {
+        match token {
+            Token::Number(value, _) => Value::Number(*value),
+            Token::BigNumber(digits, _) => Self::big_number(digits.parse().expect("The lexer should only let digits through")),
+            Token::Float(value, _) => Value::Float(*value),
+            Token::Bool(value, _) => Value::Bool(*value),
+            // the lexer has taken the quotes off and decoded the escapes already
+            Token::String(text, _) => Value::String(text.clone()),
+            _ => unreachable!("not a literal: {:?}", token),
+        }
+    }
+
+    fn default_value(returns: Type) -> Value {
+        match returns {
+            Type::Number => Value::Number(0),
+            Type::Float => Value::Float(0.0),
+            Type::String => Value::String(String::new()),
+            Type::Bool => Value::Bool(false),
+        }
+    }
+
+    fn parse_input(&self, line: &str, expected: Type) -> Option<Value> {
+        match expected {
+            Type::Number if self.symbols.numbers == NumberMode::Big => line.parse().ok().map(Self::big_number),
+            Type::Number => line.parse().ok().map(Value::Number),
+            Type::Float => line.parse().ok().map(Value::Float),
+            Type::Bool => line.parse().ok().map(Value::Bool),
+            Type::String => Some(Value::String(line.to_string())),
+        }
+    }
+
+    fn describe_input(expected: Type) -> &'static str {
+        match expected {
+            Type::Number => "a whole number",
+            Type::Float => "a number",
+            Type::String => "some text",
+            Type::Bool => "true or false",
+        }
+    }
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) | Value::BigNumber(_) => "Number",
+            Value::Float(_) => "Float",
+            Value::String(_) => "String",
+            Value::Bool(_) => "Bool",
+            Value::Array(_) => "Array",
+        }
+    }
+
+    fn as_big_number(&self) -> Option<PrimalInt> {
+        match self {
+            Value::Number(value) => Some(PrimalInt::from(*value)),
+            Value::BigNumber(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", value),
+            Value::BigNumber(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|element| element.to_string()).collect();
+                write!(f, "({})", elements.join(", "))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::semantic::Analyzer;
+    use super::*;
+
+    fn run(source: &str, input: &str) -> Result<String, Diagnostic> {
+        run_with(source, input, NumberMode::Fixed)
+    }
+
+    fn run_with(source: &str, input: &str, numbers: NumberMode) -> Result<String, Diagnostic> {
+        let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let symbols = Analyzer::with_numbers(numbers).analyze(&ast).unwrap();
+
+        let input = input.to_string();
+
+        // as deep a stack as `primal run` gives it
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let mut output: Vec<u8> = vec![];
+                Interpreter::new(symbols, Cursor::new(input), &mut output).run(&ast)?;
+
+                Ok(String::from_utf8(output).unwrap())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn print_values() {
+        let source = "LET x = 2 + 3 * 4\nPRINT x\nPRINT \"Hello, World!\"\nPRINT true\n";
+
+        assert_eq!(run(source, "").unwrap(), "14\nHello, World!\ntrue\n");
+    }
+
+    #[test]
+    fn loop_and_branch() {
+        let source = "
+LET x = 0
+WHILE x < 5
+DO
+    IF x % 2 == 0 AND NOT (x == 2) THEN
+        PRINT x
+    ENDIF
+    LET x = x + 1
+ENDWHILE
+";
+
+        assert_eq!(run(source, "").unwrap(), "0\n4\n");
+    }
+
+    #[test]
+    fn take_the_first_matching_branch() {
+        let source = "
+LET x = 0
+WHILE x < 4
+DO
+    IF x == 0 THEN
+        PRINT \"zero\"
+    ELSEIF x < 2 THEN
+        PRINT \"one\"
+    ELSEIF x < 3 THEN
+        PRINT \"two\"
+    ELSE
+        PRINT \"many\"
+    ENDIF
+    LET x = x + 1
+ENDWHILE
+";
+
+        assert_eq!(run(source, "").unwrap(), "zero\none\ntwo\nmany\n");
+    }
+
+    #[test]
+    fn count_up_and_down() {
+        let source = "
+FOR i = 1 TO 10 STEP 3
+    PRINT i
+NEXT i
+FOR i = 3 TO 1 STEP -1
+    PRINT i
+NEXT
+PRINT i
+FOR j = 5 TO 1
+    PRINT j
+NEXT
+PRINT j
+FOR k = 1 TO 10
+    LET k = k * 4
+    PRINT k
+NEXT
+";
+
+        assert_eq!(run(source, "").unwrap(), "1\n4\n7\n10\n3\n2\n1\n1\n5\n4\n20\n");
+    }
+
+    #[test]
+    fn stop_counting_at_the_largest_number() {
+        let source = "FOR i = 2147483646 TO 2147483647\nPRINT i\nNEXT\nLET step = 0\nFOR i = 1 TO 2 STEP step\nNEXT\n";
+
+        assert_eq!(run(&source[..source.find("LET").unwrap()], "").unwrap(), "2147483646\n2147483647\n");
+        assert_eq!(run(source, "").unwrap_err().message, "the STEP of a FOR loop must not be 0");
+    }
+
+    #[test]
+    fn leave_loops_early() {
+        let source = "
+LET x = 0
+WHILE true
+DO
+    LET x = x + 1
+    IF x % 2 == 0 THEN
+        CONTINUE
+    ELSEIF x > 6 THEN
+        BREAK
+    ENDIF
+    FOR i = 1 TO 3
+        IF i == 2 THEN
+            CONTINUE
+        ENDIF
+        IF i * x > 10 THEN
+            BREAK
+        ENDIF
+        LET product = i * x
+        PRINT product
+    NEXT
+ENDWHILE
+";
+
+        assert_eq!(run(source, "").unwrap(), "1\n3\n3\n9\n5\n");
+    }
+
+    #[test]
+    fn call_subs() {
+        let source = "
+SUB fib(n)
+    IF n < 2 THEN
+        RETURN n
+    ENDIF
+    RETURN fib(n - 1) + fib(n - 2)
+ENDSUB
+SUB greet(name, times)
+    FOR i = 1 TO times
+        IF i == 3 THEN
+            RETURN
+        ENDIF
+        PRINT name
+    NEXT
+ENDSUB
+SUB is_even(n)
+    IF n % 2 == 0 THEN
+        RETURN true
+    ENDIF
+ENDSUB
+LET n = 10
+LET result = fib(n)
+PRINT result
+CALL greet(\"hi\", 5)
+IF is_even(n) AND NOT is_even(n + 1) THEN
+    PRINT n
+ENDIF
+";
+
+        assert_eq!(run(source, "").unwrap(), "55\nhi\nhi\n10\n");
+    }
+
+    #[test]
+    fn stop_endless_recursion() {
+        let source = "SUB forever(n)\nRETURN forever(n + 1)\nENDSUB\nLET x = forever(0)\n";
+
+        assert_eq!(run(source, "").unwrap_err().message, "too many nested calls, does `forever` call itself forever?");
+    }
+
+    #[test]
+    fn jump_between_lines() {
+        let source = "
+10 LET i = 0
+20 GOSUB 100
+30 IF i < 3 THEN
+40     GOTO 20
+50 ENDIF
+60 GOTO 200
+100 LET i = i + 1
+110 PRINT i
+120 RETURN
+200 PRINT \"done\"
+";
+
+        assert_eq!(run(source, "").unwrap(), "1\n2\n3\ndone\n");
+        assert_eq!(run("GOSUB 10\n10 RETURN\n", "").unwrap_err().message, "`RETURN` without a `GOSUB` to go back to");
+    }
+
+    #[test]
+    fn use_arrays() {
+        let source = "
+DIM squares(4)
+FOR i = 0 TO 4
+    LET squares(i) = i * i
+NEXT
+LET total = 0
+FOR i = 0 TO 4
+    LET total = total + squares(i)
+NEXT
+PRINT total
+DIM names(1)
+LET first = names(0)
+LET names(1) = \"Bob\"
+LET second = names(1)
+PRINT second
+";
+
+        assert_eq!(run(source, "").unwrap(), "30\nBob\n");
+    }
+
+    #[test]
+    fn calculate_with_floats() {
+        let source = "LET speed = 2.5\nLET time = 4\nLET distance = speed * time\nPRINT distance\nLET half = 0.0\nLET half = time / 8\nPRINT half\nIF distance > 9 AND - speed < 0 THEN\nPRINT 0.1\nENDIF\n";
+
+        assert_eq!(run(source, "").unwrap(), "10\n0\n0.1\n");
+    }
+
+    #[test]
+    fn print_lists() {
+        let source = "LET x = 41\nPRINT \"x + 1 = \"; x + 1\nPRINT \"é\", x, \"fourteen chars\", 1\nPRINT \"no newline \";\nPRINT \"yet\"\nPRINT\nPRINT x,\n";
+
+        assert_eq!(
+            run(source, "").unwrap(),
+            "x + 1 = 42\né             41            fourteen chars              1\nno newline yet\n\n41            "
+        );
+    }
+
+    #[test]
+    fn work_with_strings() {
+        let source = "LET cheese = \"Gruyère\"\nLET size = LEN(cheese)\nPRINT size\nLET cut = LEFT$(cheese, 3) + \"-\" + MID$(cheese, 4, 2) + \"-\" + RIGHT$(cheese, 2)\nPRINT cut\nLET rest = MID$(cheese, 6)\nPRINT rest\nLET n = VAL(\" 41 wheels\") + 1\nLET text = STR$(n) + STR$(2.5)\nPRINT text\n";
+
+        assert_eq!(run(source, "").unwrap(), "7
+Gru-yè-re
+re
+422.5
+");
+
+        let diagnostic = run("LET s = \"abc\"\nLET n = - 1\nLET t = LEFT$(s, n)\n", "").unwrap_err();
+        assert_eq!(diagnostic.message, "LEFT$ takes no negative number of characters, found -1");
+        assert_eq!(diagnostic.span, Span::new(34, 3, 9, 11));
+        assert_eq!(run("LET n = VAL(\"99999999999\")\n", "").unwrap_err().message, "99999999999 does not fit in a Number");
+        assert_eq!(run_with("LET n = VAL(\"99999999999\") + 1\nPRINT n\n", "", NumberMode::Big).unwrap(), "100000000000\n");
+    }
+
+    #[test]
+    fn grow_past_i32_with_big_numbers() {
+        let source = "LET x = 2147483647\nLET x = x + 1\nPRINT x\nLET y = x * x - 4611686018427387904 + 3\nPRINT y\nLET z = - 100000000000000000000 / 7\nPRINT z\nDIM a(y)\nINPUT \"?\" n\nPRINT n\n";
+
+        assert_eq!(run_with(source, "12345678901234567890\n", NumberMode::Big).unwrap(), "2147483648\n3\n-14285714285714285714\n?\n12345678901234567890\n");
+        assert_eq!(run(&source[..source.find("LET y").unwrap()], "").unwrap_err().message, "integer overflow");
+
+        let diagnostic = run_with("DIM a(2147483648)\n", "", NumberMode::Big).unwrap_err();
+        assert_eq!(diagnostic.message, "2147483648 is too big, FOR loops, array sizes and indices have to fit in 32 bits");
+    }
+
+    #[test]
+    fn report_indices_out_of_bounds() {
+        let diagnostic = run("DIM a(2)\nLET i = 3\nLET a(i) = 1\n", "").unwrap_err();
+
+        assert_eq!(diagnostic.message, "index 3 is out of bounds for `a`, which has 3 elements");
+        assert_eq!(diagnostic.span, Span::new(25, 3, 7, 1));
+        assert_eq!(
+            run("DIM a(-1)\n", "").unwrap_err().message,
+            "the last index of an array must not be negative, found -1"
+        );
+    }
+
+    #[test]
+    fn read_input() {
+        let source = "INPUT \"How much cheese?\" cheese\nLET cheese = cheese * 2\nPRINT cheese\nINPUT \"Name?\" name\nPRINT name\n";
+
+        assert_eq!(run(source, "21\nGouda\n").unwrap(), "How much cheese?\n42\nName?\nGouda\n");
+    }
+
+    #[test]
+    fn ask_again_for_invalid_input() {
+        let source = "INPUT \"Number?\" n\nLET n = n + 1\nPRINT n\n";
+
+        assert_eq!(run(source, "many\n 7 \n").unwrap(), "Number?\nPlease enter a whole number.\nNumber?\n8\n");
+        let diagnostic = run(source, "many\n").unwrap_err();
+        assert_eq!(diagnostic.message, "expected a whole number for `n`, but the input has ended");
+        assert_eq!(diagnostic.span, Span::new(0, 1, 1, 17));
+    }
+
+    #[test]
+    fn report_division_by_zero() {
+        let source = "LET x = 0\nLET y = 10 / x\n";
+        let diagnostic = run(source, "").unwrap_err();
+
+        assert_eq!(diagnostic.message, "division by zero");
+        assert_eq!(diagnostic.span, Span::new(18, 2, 9, 6));
+    }
+}
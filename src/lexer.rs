@@ -1,3 +1,7 @@
+use std::fmt;
+use crate::builtins::Builtin;
+use crate::diagnostic::{Diagnostic, Span};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Print,
@@ -5,11 +9,26 @@ pub enum Token {
     Let,
     If,
     Then,
+    Else,
+    Elseif,
     While,
     Do,
     End,
     Endif,
     Endwhile,
+    For,
+    To,
+    Step,
+    Next,
+    Break,
+    Continue,
+    Sub,
+    Endsub,
+    Call,
+    Return,
+    Goto,
+    Gosub,
+    Dim,
 
     Assign,
 
@@ -29,11 +48,22 @@ pub enum Token {
     Divide,
     Modulo,
 
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+
     // u8 is an ID for simpler matching
     Bool(bool, u8),
+    // the text of a string literal, without its quotes and with the escapes decoded
     String(String, u8),
     Number(i32, u8),
+    // a whole number which does not fit in an i32, for `--bigint`
+    BigNumber(String, u8),
+    Float(f64, u8),
     Identifier(String, u8),
+    // a line number in front of a statement, e.g. "10 PRINT x"
+    Label(i32),
 
     Newline,
 }
@@ -42,79 +72,350 @@ pub const BOOL_ID: u8 = 1;
 pub const STRING_ID: u8 = 2;
 pub const NUMBER_ID: u8 = 4;
 pub const IDENTIFIER_ID: u8 = 8;
+pub const FLOAT_ID: u8 = 16;
+
+/// A value together with the place in the source it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+// how the token is written in primal source code, used in diagnostics
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Newline => write!(f, "end of line"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            token => write!(f, "{}", Lexer::convert_token_to_string(token.clone())),
+        }
+    }
+}
+
+
+/// How a program may be written, chosen with `--dialect` or a `#!dialect` pragma on its first line.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Dialect {
+    /// keywords in capitals only, `print` is just a name
+    #[default]
+    Strict,
+    /// keywords, string functions and `true`/`false` in any case, `Print` is `PRINT`
+    CaseInsensitive,
+    /// old BASIC: like CaseInsensitive, with a line number going up in front of every line and REM comments
+    Classic,
+}
+
+impl Dialect {
+    pub fn find(name: &str) -> Option<Dialect> {
+        match name {
+            "strict" => Some(Dialect::Strict),
+            "case-insensitive" => Some(Dialect::CaseInsensitive),
+            "classic" => Some(Dialect::Classic),
+            _ => None,
+        }
+    }
+}
 
+pub const DIALECT_PRAGMA: &str = "#!dialect";
 
-pub struct Lexer {}
+/// The start of every name the emitter makes up for itself, no variable, array or SUB may use it.
+pub const RESERVED_PREFIX: &str = "primal_";
+
+pub struct Lexer {
+    dialect: Dialect,
+}
 
 impl Lexer {
-    // main lexer function
-    pub fn tokenize(contents: String) -> Vec<Token> {
-        contents
-            .lines()
-            .map(Self::tokenize_line)
-            .filter(|tokens| !tokens.is_empty()) // Care only about non-empty lines.
-            .flatten() // possible thanks to Token::Newline
-            .collect()
+    pub fn new() -> Lexer {
+        Self::with_dialect(Dialect::default())
     }
 
-    fn return_good_token(option_slice: Option<&str>) -> Vec<&str> {
-        if let Some(unit) = option_slice { 
-            return vec![unit];
-        }
-        vec![]
+    pub fn with_dialect(dialect: Dialect) -> Lexer {
+        Lexer { dialect }
     }
 
-    fn tokenize_line(line: &str) -> Vec<Token> {
-        let mut lexemes: Vec<&str> = vec![];
-        let mut lexeme_start = 0;
-        let mut is_lexeme = false;
-        let mut is_comment = false;
-        let mut is_string = false;
+    // main lexer function
+    pub fn tokenize(&mut self, contents: String) -> Result<Vec<Spanned<Token>>, Vec<Diagnostic>> {
+        let mut tokens: Vec<Spanned<Token>> = vec![];
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let mut line_offset = 0;
+        let mut last_label = None;
 
-        line.chars().enumerate().for_each(|(index, c)| {
-            if is_comment { return; }
+        // the file knows best how it is written
+        match Self::pragma(&contents) {
+            Ok(Some(dialect)) => self.dialect = dialect,
+            Ok(None) => {}
+            Err(diagnostic) => return Err(vec![diagnostic]),
+        }
 
-            match c {
-                ' ' | '\n' if is_lexeme && !is_string => {
-                    let mut token_vector = Self::return_good_token(line.get(lexeme_start..=index - 1));
-                    lexemes.append(&mut token_vector);
-                    
-                    is_lexeme = false;
-                }, 
-                ' ' | '\n' => {}, // catch the rest of whitespace
-                '#' => {
-                    is_comment = true;
-                },
-                _ => {
-                    if !is_lexeme {
-                        is_lexeme = true;
-                        lexeme_start = index;
-                    }
+        // keep the line endings around to know the byte offset of every line
+        let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+        let mut index = 0;
 
-                    if c == '"' {
-                        is_string = !is_string;
-                    }
+        while index < lines.len() {
+            let line_number = index + 1;
+            let mut line_end = line_offset + lines[index].len();
+            index += 1;
+
+            // a string left open at the end of a line goes on over the next ones
+            while index < lines.len() && self.ends_inside_string(&contents[line_offset..line_end]) {
+                line_end += lines[index].len();
+                index += 1;
+            }
 
-                    if is_lexeme && index == line.len() - 1{
-                        let mut token_vector = Self::return_good_token(line.get(lexeme_start..=index));
-                        lexemes.append(&mut token_vector);
+            let text = contents[line_offset..line_end].trim_end_matches(['\n', '\r']);
 
-                        is_lexeme = false;
+            match self.tokenize_line(text, line_number, line_offset) {
+                Ok(mut line_tokens) => {
+                    if self.dialect == Dialect::Classic {
+                        if let Err(diagnostic) = Self::check_line_number(&line_tokens, &mut last_label) {
+                            diagnostics.push(diagnostic);
+                        }
                     }
+                    tokens.append(&mut line_tokens);
                 }
+                Err(mut line_diagnostics) => diagnostics.append(&mut line_diagnostics),
             }
-        });
+            line_offset = line_end;
+        }
 
-        lexemes.push("\n"); // add NEWLINE at the end
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        Ok(tokens)
+    }
 
-        lexemes
+    fn tokenize_line(&self, line: &str, line_number: usize, line_offset: usize) -> Result<Vec<Spanned<Token>>, Vec<Diagnostic>> {
+        let mut lexemes = self.scan_line(line);
+
+        // REM is a comment like `#`, there is no name left to clash with it
+        if self.dialect == Dialect::Classic {
+            if let Some(remark) = lexemes.iter().position(|(_, lexeme)| lexeme.eq_ignore_ascii_case("REM")) {
+                lexemes.truncate(remark);
+            }
+        }
+        lexemes.push((line.len(), "\n")); // add NEWLINE at the end
+
+        let mut tokens: Vec<Spanned<Token>> = vec![];
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+
+        for (start, lexeme) in lexemes {
+            // only a string goes over several lines, it is underlined up to the end of its first one
+            let before = &line[..start];
+            let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+            let underlined = match lexeme.starts_with('"') {
+                true => lexeme.split('\n').next().unwrap_or(lexeme),
+                false => lexeme,
+            };
+            let span = Span::new(
+                line_offset + start,
+                line_number + before.matches('\n').count(),
+                before[line_start..].chars().count() + 1,
+                underlined.chars().count(),
+            );
+
+            if lexeme.starts_with('"') {
+                if let Err(message) = Self::string_contents(lexeme) {
+                    diagnostics.push(Diagnostic::error(message, span));
+                    continue;
+                }
+            }
+
+            match self.identify(lexeme) {
+                Some(token) => tokens.push(Spanned { node: token, span }),
+                None => diagnostics.push(Diagnostic::error(
+                    format!("token not recognized: `{}`", lexeme),
+                    span,
+                )),
+            }
+        }
+
+        // no statement starts with a number, so one in front is a line number
+        if let Some(first) = tokens.first_mut() {
+            if let Token::Number(number, _) = first.node {
+                if number >= 0 {
+                    first.node = Token::Label(number);
+                }
+            }
+        }
+
+        diagnostics.append(&mut Self::misused_names(&tokens));
+
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        Ok(tokens)
+    }
+
+    // a keyword where the line needs a name, like "LET TO = 1", is called out before the parser gets puzzled,
+    // and so is any name the generated code could mix up with its own
+    fn misused_names(tokens: &[Spanned<Token>]) -> Vec<Diagnostic> {
+        let is_sub = tokens
             .iter()
-            .map(|lexeme| {
-                Self::identify_token(lexeme)
+            .find(|token| !matches!(token.node, Token::Label(_)))
+            .is_some_and(|token| token.node == Token::Sub);
+
+        let reserved = tokens.iter().filter_map(|token| match &token.node {
+            Token::Identifier(name, _) if name.starts_with(RESERVED_PREFIX) => Some(Diagnostic::error(
+                format!("`{}` starts with `{}`, which the generated code keeps for itself, pick another name", name, RESERVED_PREFIX),
+                token.span,
+            )),
+            _ => None,
+        });
+
+        tokens
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(index, _)| match &tokens[index - 1].node {
+                Token::Let | Token::Dim | Token::Sub | Token::For | Token::Next => true,
+                // INPUT "message" name
+                Token::String(..) => *index >= 2 && tokens[index - 2].node == Token::Input,
+                // SUB name(parameter, ...)
+                Token::LeftParen | Token::Comma => is_sub,
+                _ => false,
             })
+            .map(|(_, token)| token)
+            .filter(|token| !matches!(token.node, Token::Identifier(..)) && Self::is_keyword(&token.node.to_string()))
+            .map(|token| Diagnostic::error(format!("`{}` is a keyword, pick another name", token.node), token.span))
+            .chain(reserved)
             .collect()
     }
 
+    // the line cut into lexemes one character at a time, with their byte offsets;
+    // no spaces are needed between them, "x>=10" is `x`, `>=` and `10`
+    fn scan_line<'a>(&self, line: &'a str) -> Vec<(usize, &'a str)> {
+        let mut lexemes: Vec<(usize, &str)> = vec![];
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            let next = chars.peek().map(|(_, next)| *next);
+
+            let end = match c {
+                c if c.is_whitespace() => continue,
+                '#' => break, // the rest of the line is a comment
+                // without a closing quote the string takes the rest of the line, tokenize_line reports it
+                '"' => start + Self::string_length(&line[start..]).unwrap_or(line.len() - start),
+                '=' | '!' | '<' | '>' if next == Some('=') => start + 2,
+                // "-1" is a number of its own, unless it comes right after an operand like in "x -1"
+                '-' if next.is_some_and(|next| next.is_ascii_digit()) && !self.ends_operand(lexemes.last()) => {
+                    Self::word_end(line, start + 1)
+                }
+                c if Self::is_word_char(c) => Self::word_end(line, start),
+                // operators, parentheses and anything unknown, on their own
+                c => start + c.len_utf8(),
+            };
+
+            lexemes.push((start, &line[start..end]));
+
+            while chars.next_if(|(index, _)| *index < end).is_some() {}
+        }
+        lexemes
+    }
+
+    // letters, digits and `_` of identifiers and numbers, the `.` of Floats and the `$` of string functions
+    fn is_word_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')
+    }
+
+    fn word_end(line: &str, start: usize) -> usize {
+        line[start..]
+            .char_indices()
+            .find(|(_, c)| !Self::is_word_char(*c))
+            .map_or(line.len(), |(index, _)| start + index)
+    }
+
+    fn ends_operand(&self, lexeme: Option<&(usize, &str)>) -> bool {
+        lexeme
+            .and_then(|(_, lexeme)| self.identify(lexeme))
+            .is_some_and(|token| Self::is_operand(&token) || token == Token::RightParen)
+    }
+
+    // "#!dialect classic" on the first line
+    fn pragma(contents: &str) -> Result<Option<Dialect>, Diagnostic> {
+        let first_line = contents.lines().next().unwrap_or_default();
+
+        let Some(rest) = first_line.strip_prefix(DIALECT_PRAGMA) else {
+            return Ok(None);
+        };
+
+        let name = rest.trim();
+        let start = first_line.len() - rest.trim_start().len();
+
+        Dialect::find(name).map(Some).ok_or_else(|| {
+            Diagnostic::error(
+                format!("unknown dialect `{}`, expected strict, case-insensitive or classic", name),
+                Span::new(start, 1, first_line[..start].chars().count() + 1, name.chars().count()),
+            )
+        })
+    }
+
+    // every line but the empty ones starts with a line number, each bigger than the one before
+    fn check_line_number(tokens: &[Spanned<Token>], last_label: &mut Option<i32>) -> Result<(), Diagnostic> {
+        let first = &tokens[0];
+
+        match first.node {
+            Token::Newline => Ok(()),
+            Token::Label(number) => match *last_label {
+                Some(last) if number <= last => Err(Diagnostic::error(
+                    format!("line numbers go up, `{}` comes after `{}`", number, last),
+                    first.span,
+                )),
+                _ => {
+                    *last_label = Some(number);
+                    Ok(())
+                }
+            },
+            _ => Err(Diagnostic::error("a classic program starts every line with its number".to_string(), first.span)),
+        }
+    }
+
+    // identify_token, once the dialect had its say about upper and lower case
+    fn identify(&self, lexeme: &str) -> Option<Token> {
+        if self.dialect != Dialect::Strict {
+            let lower = lexeme.to_ascii_lowercase();
+            let upper = lexeme.to_ascii_uppercase();
+
+            if lower == "true" || lower == "false" {
+                return Self::identify_token(&lower);
+            }
+            if Self::is_keyword(&upper) {
+                return Self::identify_token(&upper);
+            }
+        }
+        Self::identify_token(lexeme)
+    }
+
+    /// Whether the word is a keyword or the name of a string function, as they are written in capitals.
+    pub fn is_keyword(word: &str) -> bool {
+        word.starts_with(|c: char| c.is_ascii_alphabetic())
+            && (Builtin::find(word).is_some() || !matches!(Self::identify_token(word), Some(Token::Identifier(..)) | None))
+    }
+
+    fn ends_inside_string(&self, text: &str) -> bool {
+        self.scan_line(text)
+            .last()
+            .is_some_and(|(_, lexeme)| lexeme.starts_with('"') && Self::string_length(lexeme).is_none())
+    }
+
+    // bytes of the string at the start of the text, both quotes included; a backslash escapes the
+    // character after it, so "\"" does not end the string
+    fn string_length(text: &str) -> Option<usize> {
+        let mut is_escaped = false;
+
+        for (index, c) in text.char_indices().skip(1) {
+            match c {
+                _ if is_escaped => is_escaped = false,
+                '\\' => is_escaped = true,
+                '"' => return Some(index + 1),
+                _ => {}
+            }
+        }
+        None
+    }
+
     fn is_valid_identifier(lexeme: &str) -> bool {
         for (index, c) in lexeme.chars().enumerate() {
             match c {
@@ -131,16 +432,89 @@ impl Lexer {
         lexeme.parse::<i32>().is_ok()
     }
 
-    /// Rust automatically sanitises quotes -> \"text\"
-    fn is_valid_string_literal(lexeme: &str) -> bool {
-        if Some('"') == lexeme.chars().nth(0) && Some('"') == lexeme.chars().nth_back(0) {
-            return true;
+    // too big for is_valid_number, but still just digits: [-]?[0-9]+
+    fn is_valid_big_number(lexeme: &str) -> bool {
+        let digits = lexeme.strip_prefix('-').unwrap_or(lexeme);
+
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    }
+
+    // digits on both sides of the point: [-]?[0-9]+.[0-9]+
+    fn is_valid_float(lexeme: &str) -> bool {
+        let digits = lexeme.strip_prefix('-').unwrap_or(lexeme);
+        let Some((whole, fraction)) = digits.split_once('.') else {
+            return false;
+        };
+        let is_digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+
+        is_digits(whole) && is_digits(fraction) && lexeme.parse::<f64>().is_ok_and(f64::is_finite)
+    }
+
+    // the text between the quotes with its escapes decoded: \n, \t, \", \\ and \u{e9} for any character
+    fn string_contents(lexeme: &str) -> Result<String, String> {
+        if Self::string_length(lexeme) != Some(lexeme.len()) {
+            return Err("this string has no closing quote".to_string());
         }
-        false
+
+        // a line break inside the string is a newline, whatever the file uses
+        let inside = lexeme[1..lexeme.len() - 1].replace("\r\n", "\n");
+        let mut text = String::new();
+        let mut chars = inside.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('"') => text.push('"'),
+                Some('\\') => text.push('\\'),
+                Some('u') => {
+                    let rest = chars.as_str();
+                    let code = rest
+                        .strip_prefix('{')
+                        .and_then(|rest| rest.split_once('}'))
+                        .map(|(code, _)| code)
+                        .ok_or("`\\u` takes the code of a character in braces, like `\\u{e9}`")?;
+                    let character = Some(code)
+                        .filter(|code| (1..=6).contains(&code.len()) && code.chars().all(|c| c.is_ascii_hexdigit()))
+                        .and_then(|code| u32::from_str_radix(code, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or(format!("`\\u{{{}}}` is not a character", code))?;
+
+                    text.push(character);
+                    chars = rest[code.len() + 2..].chars();
+                }
+                Some(other) => return Err(format!("unknown escape `\\{}` in a string, write `\\\\` for a backslash", other)),
+                None => unreachable!("the closing quote was escaped"),
+            }
+        }
+        Ok(text)
+    }
+
+    // a string literal again, escaping what string_contents decodes and any other control character
+    fn quote(text: &str) -> String {
+        let mut literal = String::from("\"");
+
+        for c in text.chars() {
+            match c {
+                '\n' => literal.push_str("\\n"),
+                '\t' => literal.push_str("\\t"),
+                '"' => literal.push_str("\\\""),
+                '\\' => literal.push_str("\\\\"),
+                c if c.is_control() => literal.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => literal.push(c),
+            }
+        }
+        literal.push('"');
+        literal
     }
 
-    fn identify_token(lexeme: &str) -> Token {
-        match lexeme {
+    fn identify_token(lexeme: &str) -> Option<Token> {
+        let token = match lexeme {
             "\n" => Token::Newline,
 
             "PRINT" => Token::Print,
@@ -148,11 +522,26 @@ impl Lexer {
             "LET" => Token::Let,
             "IF" => Token::If,
             "THEN" => Token::Then,
+            "ELSE" => Token::Else,
+            "ELSEIF" => Token::Elseif,
             "WHILE" => Token::While,
             "DO" => Token::Do,
             "END" => Token::End,
             "ENDIF" => Token::Endif,
             "ENDWHILE" => Token::Endwhile,
+            "FOR" => Token::For,
+            "TO" => Token::To,
+            "STEP" => Token::Step,
+            "NEXT" => Token::Next,
+            "BREAK" => Token::Break,
+            "CONTINUE" => Token::Continue,
+            "SUB" => Token::Sub,
+            "ENDSUB" => Token::Endsub,
+            "CALL" => Token::Call,
+            "RETURN" => Token::Return,
+            "GOTO" => Token::Goto,
+            "GOSUB" => Token::Gosub,
+            "DIM" => Token::Dim,
             "AND" => Token::And,
             "OR" => Token::Or,
             "NOT" => Token::Not,
@@ -161,7 +550,7 @@ impl Lexer {
 
             "==" => Token::Equals,
             "!=" => Token::NotEquals,
-            "=>" => Token::MoreThanEquals,
+            ">=" => Token::MoreThanEquals,
             ">" => Token::MoreThan,
             "<=" => Token::LessThanEquals,
             "<" => Token::LessThan,
@@ -172,18 +561,25 @@ impl Lexer {
             "/" => Token::Divide,
             "%" => Token::Modulo,
 
+            "(" => Token::LeftParen,
+            ")" => Token::RightParen,
+            "," => Token::Comma,
+            ";" => Token::Semicolon,
+
             "true" => Token::Bool(true, BOOL_ID),
             "false" => Token::Bool(false, BOOL_ID),
 
-            x if Self::is_valid_string_literal(x)  => Token::String(x.to_string(), STRING_ID),
+            x if x.starts_with('"') => Token::String(Self::string_contents(x).ok()?, STRING_ID),
             x if Self::is_valid_number(x) => { Token::Number(x.parse().unwrap(), NUMBER_ID) },
+            x if Self::is_valid_big_number(x) => Token::BigNumber(x.to_string(), NUMBER_ID),
+            x if Self::is_valid_float(x) => Token::Float(x.parse().unwrap(), FLOAT_ID),
 
-            x if Self::is_valid_identifier(x) => Token::Identifier(x.to_string(), IDENTIFIER_ID),
+            // only the string functions may end with a `$`
+            x if Self::is_valid_identifier(x) || Builtin::find(x).is_some() => Token::Identifier(x.to_string(), IDENTIFIER_ID),
 
-            &_ => {
-                panic!("Token not recognized: {:?}", lexeme);
-            },
-        }
+            &_ => return None,
+        };
+        Some(token)
     }
 
     pub fn convert_token_to_string(token: Token) -> String {
@@ -194,18 +590,33 @@ impl Lexer {
             Token::Let => "LET".to_string(),
             Token::If => "IF".to_string(),
             Token::Then => "THEN".to_string(),
+            Token::Else => "ELSE".to_string(),
+            Token::Elseif => "ELSEIF".to_string(),
             Token::While => "WHILE".to_string(),
             Token::Do => "DO".to_string(),
             Token::End => "END".to_string(),
             Token::Endif => "ENDIF".to_string(),
             Token::Endwhile => "ENDWHILE".to_string(),
+            Token::For => "FOR".to_string(),
+            Token::To => "TO".to_string(),
+            Token::Step => "STEP".to_string(),
+            Token::Next => "NEXT".to_string(),
+            Token::Break => "BREAK".to_string(),
+            Token::Continue => "CONTINUE".to_string(),
+            Token::Sub => "SUB".to_string(),
+            Token::Endsub => "ENDSUB".to_string(),
+            Token::Call => "CALL".to_string(),
+            Token::Return => "RETURN".to_string(),
+            Token::Goto => "GOTO".to_string(),
+            Token::Gosub => "GOSUB".to_string(),
+            Token::Dim => "DIM".to_string(),
             Token::And => "&&".to_string(),
             Token::Or => "||".to_string(),
             Token::Not => "!".to_string(),
             Token::Assign => "=".to_string(),
             Token::Equals =>"==".to_string(),
             Token::NotEquals =>"!=".to_string(),
-            Token::MoreThanEquals =>"=>".to_string(),
+            Token::MoreThanEquals =>">=".to_string(),
             Token::MoreThan => ">".to_string(),
             Token::LessThanEquals =>"<=".to_string(),
             Token::LessThan => "<".to_string(),
@@ -214,11 +625,19 @@ impl Lexer {
             Token::Times => "*".to_string(),
             Token::Divide => "/".to_string(),
             Token::Modulo => "%".to_string(),
+            Token::LeftParen => "(".to_string(),
+            Token::RightParen => ")".to_string(),
+            Token::Comma => ",".to_string(),
+            Token::Semicolon => ";".to_string(),
             Token::Bool(true, _) => "true".to_string(),
             Token::Bool(false, _) => "false".to_string(),
-            Token::String(value, _) => value,
+            Token::String(text, _) => Self::quote(&text),
             Token::Number(value, _) => value.to_string(),
+            Token::BigNumber(digits, _) => digits,
+            // Debug keeps the point, "1.0" and not "1"
+            Token::Float(value, _) => format!("{:?}", value),
             Token::Identifier(value, _) => value,
+            Token::Label(value) => value.to_string(),
         }
     }
 
@@ -287,6 +706,8 @@ impl Lexer {
             Token::Bool(_, _) |
             Token::String(_, _) |
             Token::Number(_, _) |
+            Token::BigNumber(_, _) |
+            Token::Float(_, _) |
             Token::Identifier(_, _)
         )
     }
@@ -299,6 +720,12 @@ impl Lexer {
     }
 }
 
+impl Default for Lexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 
 
 #[cfg(test)]
@@ -309,19 +736,18 @@ mod tests {
 
     #[test]
     fn identify_string() {
-        let x = "\"Hello\"with quotes\'in between\"";
+        let x = "\"Hello\\\"with quotes\'in between\"";
         let result = self::Lexer::identify_token(x);
 
-        assert_eq!(result, Token::String(x.to_string(), STRING_ID), "Couldn't identify a string");
+        assert_eq!(result, Some(Token::String("Hello\"with quotes\'in between".to_string(), STRING_ID)), "Couldn't identify a string");
+        assert_eq!(self::Lexer::identify_token("\"Hello\"with quotes\""), None, "A quote inside a string needs a backslash");
     }
 
     #[test]
-    #[should_panic]
     fn not_a_string() {
         let x = "\"I dont have an end quote..";
 
-        // should panic here
-        let _ = self::Lexer::identify_token(x);
+        assert_eq!(self::Lexer::identify_token(x), None);
     }
 
     #[test]
@@ -330,20 +756,36 @@ mod tests {
 
         let result = self::Lexer::identify_token(x);
 
-        assert_eq!(result, Token::Number(x.parse().unwrap(), NUMBER_ID), "Couldn't identify a normal number");
+        assert_eq!(result, Some(Token::Number(x.parse().unwrap(), NUMBER_ID)), "Couldn't identify a normal number");
     }
 
     #[test]
-    #[should_panic]
     fn identify_a_float() {
         let x = "-10923,10293";
         let y = "999,420";
 
-        let result1 = self::Lexer::identify_token(x);
-        let result2 = self::Lexer::identify_token(y);
+        assert_eq!(self::Lexer::identify_token(x), None, "Floats use a point, not a comma");
+        assert_eq!(self::Lexer::identify_token(y), None, "Floats use a point, not a comma");
+    }
+
+    #[test]
+    fn identify_a_big_number() {
+        let x = "2147483648";
+        let y = "-123456789012345678901234567890";
+
+        assert_eq!(self::Lexer::identify_token(x), Some(Token::BigNumber(x.to_string(), NUMBER_ID)));
+        assert_eq!(self::Lexer::identify_token(y), Some(Token::BigNumber(y.to_string(), NUMBER_ID)));
+        assert_eq!(self::Lexer::identify_token("2147483647"), Some(Token::Number(i32::MAX, NUMBER_ID)));
+    }
 
-        assert_eq!(result1, Token::Number(x.parse().unwrap(), NUMBER_ID), "Couldn't identify a signed float");
-        assert_eq!(result2, Token::Number(y.parse().unwrap(), NUMBER_ID), "Couldn't identify a float");
+    #[test]
+    fn identify_a_decimal_float() {
+        assert_eq!(self::Lexer::identify_token("1.5"), Some(Token::Float(1.5, FLOAT_ID)));
+        assert_eq!(self::Lexer::identify_token("-0.25"), Some(Token::Float(-0.25, FLOAT_ID)));
+        assert_eq!(self::Lexer::identify_token("1."), None);
+        assert_eq!(self::Lexer::identify_token(".5"), None);
+        assert_eq!(self::Lexer::identify_token("1.5.2"), None);
+        assert_eq!(Lexer::convert_token_to_string(Token::Float(2.0, FLOAT_ID)), "2.0");
     }
 
     #[test]
@@ -351,26 +793,37 @@ mod tests {
         let x = "abcdefghijkmnlopqrstuvwxyzABCDEFGHIJKMNLOPQRSTUVWXYZ1234567890_";
         let result = self::Lexer::identify_token(x);
 
-        assert_eq!(result, Token::Identifier(x.to_string(), IDENTIFIER_ID), "Identifier was not found");
+        assert_eq!(result, Some(Token::Identifier(x.to_string(), IDENTIFIER_ID)), "Identifier was not found");
     }
 
     #[test]
-    #[should_panic]
     fn dont_identify_identifier() {
         // number at the start
         let x = "9_number";
         // wrong characters
         let y = "abc!this-is-bad+=!@#$%^&*";
 
-        // should panic here
-        let _ = self::Lexer::identify_token(x);
-        let _ = self::Lexer::identify_token(y);
+        assert_eq!(self::Lexer::identify_token(x), None);
+        assert_eq!(self::Lexer::identify_token(y), None);
+        // only the string functions end with a dollar
+        assert_eq!(self::Lexer::identify_token("name$"), None);
+    }
+
+    #[test]
+    fn identify_string_functions() {
+        for name in ["LEN", "MID$", "LEFT$", "RIGHT$", "STR$", "VAL"] {
+            assert_eq!(self::Lexer::identify_token(name), Some(Token::Identifier(name.to_string(), IDENTIFIER_ID)));
+        }
     }
 
     #[test]
     fn identify_a_line() {
         let line = "LET x = 15 \n";
-        let result = self::Lexer::tokenize_line(line);
+        let result: Vec<Token> = self::Lexer::new().tokenize_line(line, 1, 0)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.node)
+            .collect();
 
         assert_eq!(result, vec![Token::Let, Token::Identifier("x".to_string(), IDENTIFIER_ID), Token::Assign, Token::Number(15, NUMBER_ID), Token::Newline]);
     }
@@ -378,7 +831,11 @@ mod tests {
     #[test]
     fn identify_lines() {
         let lines = "LET x == IF \n WHILE PRINT = true \n";
-        let result = self::Lexer::tokenize(lines.to_string());
+        let result: Vec<Token> = self::Lexer::new().tokenize(lines.to_string())
+            .unwrap()
+            .into_iter()
+            .map(|token| token.node)
+            .collect();
 
         assert_eq!(result,
             vec![Token::Let,
@@ -393,5 +850,371 @@ mod tests {
                 Token::Newline
             ]);
     }
-}
 
+    #[test]
+    fn split_parentheses() {
+        let line = "LET x = (1 + y)*(2)";
+        let result: Vec<Token> = self::Lexer::new().tokenize_line(line, 1, 0)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.node)
+            .collect();
+
+        assert_eq!(result, vec![
+            Token::Let,
+            Token::Identifier("x".to_string(), IDENTIFIER_ID),
+            Token::Assign,
+            Token::LeftParen,
+            Token::Number(1, NUMBER_ID),
+            Token::Plus,
+            Token::Identifier("y".to_string(), IDENTIFIER_ID),
+            Token::RightParen,
+            Token::Times,
+            Token::LeftParen,
+            Token::Number(2, NUMBER_ID),
+            Token::RightParen,
+            Token::Newline,
+        ]);
+    }
+
+    #[test]
+    fn report_unknown_token_position() {
+        let lines = "PRINT 1\nLET x = @\n";
+        let result = self::Lexer::new().tokenize(lines.to_string()).unwrap_err();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].message, "token not recognized: `@`");
+        assert_eq!(result[0].span, Span::new(16, 2, 9, 1));
+    }
+
+    #[test]
+    fn remember_token_positions() {
+        let lines = "PRINT 1\n\n  LET x = \"ünï\"\n";
+        let result = self::Lexer::new().tokenize(lines.to_string()).unwrap();
+
+        let spans: Vec<Span> = result.iter().map(|token| token.span).collect();
+
+        assert_eq!(spans, vec![
+            Span::new(0, 1, 1, 5),
+            Span::new(6, 1, 7, 1),
+            Span::new(7, 1, 8, 1),
+            Span::new(8, 2, 1, 1),
+            Span::new(11, 3, 3, 3),
+            Span::new(15, 3, 7, 1),
+            Span::new(17, 3, 9, 1),
+            Span::new(19, 3, 11, 5),
+            Span::new(26, 3, 16, 1),
+        ]);
+    }
+
+    fn scan(line: &str) -> Vec<Token> {
+        self::Lexer::new().tokenize_line(line, 1, 0)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.node)
+            .collect()
+    }
+
+    fn identifier(name: &str) -> Token {
+        Token::Identifier(name.to_string(), IDENTIFIER_ID)
+    }
+
+    #[test]
+    fn scan_operators_without_spaces() {
+        assert_eq!(scan("x>=10"), vec![identifier("x"), Token::MoreThanEquals, Token::Number(10, NUMBER_ID), Token::Newline]);
+        assert_eq!(scan("a+b"), vec![identifier("a"), Token::Plus, identifier("b"), Token::Newline]);
+        assert_eq!(scan("LET x=5"), vec![Token::Let, identifier("x"), Token::Assign, Token::Number(5, NUMBER_ID), Token::Newline]);
+        assert_eq!(scan("a==b"), vec![identifier("a"), Token::Equals, identifier("b"), Token::Newline]);
+        assert_eq!(scan("a!=b<=c<d>e"), vec![
+            identifier("a"),
+            Token::NotEquals,
+            identifier("b"),
+            Token::LessThanEquals,
+            identifier("c"),
+            Token::LessThan,
+            identifier("d"),
+            Token::MoreThan,
+            identifier("e"),
+            Token::Newline,
+        ]);
+        assert_eq!(scan("x*y/2%z"), vec![
+            identifier("x"),
+            Token::Times,
+            identifier("y"),
+            Token::Divide,
+            Token::Number(2, NUMBER_ID),
+            Token::Modulo,
+            identifier("z"),
+            Token::Newline,
+        ]);
+    }
+
+    #[test]
+    fn write_greater_or_equal_the_usual_way() {
+        assert_eq!(self::Lexer::identify_token(">="), Some(Token::MoreThanEquals));
+        assert_eq!(self::Lexer::identify_token("=>"), None);
+        assert_eq!(Lexer::convert_token_to_string(Token::MoreThanEquals), ">=");
+        assert_eq!(Token::MoreThanEquals.to_string(), ">=");
+    }
+
+    #[test]
+    fn tell_negative_numbers_from_subtraction() {
+        assert_eq!(scan("x-1"), vec![identifier("x"), Token::Minus, Token::Number(1, NUMBER_ID), Token::Newline]);
+        assert_eq!(scan("x -1"), vec![identifier("x"), Token::Minus, Token::Number(1, NUMBER_ID), Token::Newline]);
+        assert_eq!(scan("(x)-1"), vec![
+            Token::LeftParen,
+            identifier("x"),
+            Token::RightParen,
+            Token::Minus,
+            Token::Number(1, NUMBER_ID),
+            Token::Newline,
+        ]);
+        assert_eq!(scan("STEP -3"), vec![Token::Step, Token::Number(-3, NUMBER_ID), Token::Newline]);
+        assert_eq!(scan("x=-2.5"), vec![identifier("x"), Token::Assign, Token::Float(-2.5, FLOAT_ID), Token::Newline]);
+        assert_eq!(scan("x*-y"), vec![identifier("x"), Token::Times, Token::Minus, identifier("y"), Token::Newline]);
+    }
+
+    #[test]
+    fn scan_strings_with_escaped_quotes() {
+        let line = r#"PRINT "say \"cheese\" # not a comment"+"\\""#;
+
+        assert_eq!(scan(line), vec![
+            Token::Print,
+            Token::String("say \"cheese\" # not a comment".to_string(), STRING_ID),
+            Token::Plus,
+            Token::String("\\".to_string(), STRING_ID),
+            Token::Newline,
+        ]);
+        assert_eq!(scan("PRINT \"a b\"# a comment"), vec![
+            Token::Print,
+            Token::String("a b".to_string(), STRING_ID),
+            Token::Newline,
+        ]);
+    }
+
+    #[test]
+    fn report_unterminated_strings() {
+        let result = self::Lexer::new().tokenize("PRINT 1\nPRINT \"abc\\\"\n".to_string()).unwrap_err();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].message, "this string has no closing quote");
+        assert_eq!(result[0].span, Span::new(14, 2, 7, 6));
+    }
+
+    #[test]
+    fn keep_words_whole() {
+        assert_eq!(scan("x2=LEFT$(name_1,3)"), vec![
+            identifier("x2"),
+            Token::Assign,
+            identifier("LEFT$"),
+            Token::LeftParen,
+            identifier("name_1"),
+            Token::Comma,
+            Token::Number(3, NUMBER_ID),
+            Token::RightParen,
+            Token::Newline,
+        ]);
+        let result = self::Lexer::new().tokenize_line("IF x>1.5THEN", 1, 0).unwrap_err();
+
+        assert_eq!(result[0].message, "token not recognized: `1.5THEN`");
+    }
+
+    #[test]
+    fn remember_positions_without_spaces() {
+        let result = self::Lexer::new().tokenize("PRINT \"ü\";a>=-1\n".to_string()).unwrap();
+        let spans: Vec<Span> = result.iter().map(|token| token.span).collect();
+
+        assert_eq!(spans, vec![
+            Span::new(0, 1, 1, 5),
+            Span::new(6, 1, 7, 3),
+            Span::new(10, 1, 10, 1),
+            Span::new(11, 1, 11, 1),
+            Span::new(12, 1, 12, 2),
+            Span::new(14, 1, 14, 2),
+            Span::new(16, 1, 16, 1),
+        ]);
+    }
+
+    #[test]
+    fn decode_string_escapes() {
+        let line = r#"PRINT "a\tb\n\"c\" \\ \u{e9}\u{1F9C0} {}""#;
+
+        assert_eq!(scan(line)[1], Token::String("a\tb\n\"c\" \\ é🧀 {}".to_string(), STRING_ID));
+        assert_eq!(Lexer::convert_token_to_string(scan(line)[1].clone()), r#""a\tb\n\"c\" \\ é🧀 {}""#);
+        assert_eq!(Lexer::convert_token_to_string(Token::String("\u{7}".to_string(), STRING_ID)), r#""\u{7}""#);
+    }
+
+    #[test]
+    fn report_bad_escapes() {
+        let messages = |line: &str| -> Vec<String> {
+            self::Lexer::new().tokenize_line(line, 1, 0).unwrap_err().into_iter().map(|error| error.message).collect()
+        };
+
+        assert_eq!(messages(r#"PRINT "C:\dos""#), vec!["unknown escape `\\d` in a string, write `\\\\` for a backslash"]);
+        assert_eq!(messages(r#"PRINT "\u00e9""#), vec!["`\\u` takes the code of a character in braces, like `\\u{e9}`"]);
+        assert_eq!(messages(r#"PRINT "\u{d800}""#), vec!["`\\u{d800}` is not a character"]);
+        assert_eq!(messages(r#"PRINT "\u{+e9}""#), vec!["`\\u{+e9}` is not a character"]);
+        assert_eq!(messages(r#"PRINT "\u{}""#), vec!["`\\u{}` is not a character"]);
+    }
+
+    #[test]
+    fn read_strings_over_several_lines() {
+        let lines = "PRINT \"one\r\ntwo\n  three\" ; x\nPRINT 1\n";
+        let result = self::Lexer::new().tokenize(lines.to_string()).unwrap();
+
+        let tokens: Vec<Token> = result.iter().map(|token| token.node.clone()).collect();
+        let spans: Vec<Span> = result.iter().map(|token| token.span).collect();
+
+        assert_eq!(tokens, vec![
+            Token::Print,
+            Token::String("one\ntwo\n  three".to_string(), STRING_ID),
+            Token::Semicolon,
+            identifier("x"),
+            Token::Newline,
+            Token::Print,
+            Token::Number(1, NUMBER_ID),
+            Token::Newline,
+        ]);
+        assert_eq!(spans, vec![
+            Span::new(0, 1, 1, 5),
+            Span::new(6, 1, 7, 5),
+            Span::new(25, 3, 10, 1),
+            Span::new(27, 3, 12, 1),
+            Span::new(28, 3, 13, 1),
+            Span::new(29, 4, 1, 5),
+            Span::new(35, 4, 7, 1),
+            Span::new(36, 4, 8, 1),
+        ]);
+    }
+
+    fn tokenize_in(dialect: Dialect, source: &str) -> Result<Vec<Token>, Vec<String>> {
+        self::Lexer::with_dialect(dialect)
+            .tokenize(source.to_string())
+            .map(|tokens| tokens.into_iter().map(|token| token.node).collect())
+            .map_err(|errors| errors.into_iter().map(|error| error.message).collect())
+    }
+
+    #[test]
+    fn keep_keywords_in_capitals_when_strict() {
+        assert_eq!(tokenize_in(Dialect::Strict, "print TRUE\n"), Ok(vec![identifier("print"), identifier("TRUE"), Token::Newline]));
+        assert!(Lexer::is_keyword("PRINT"));
+        assert!(Lexer::is_keyword("MID$"));
+        assert!(!Lexer::is_keyword("print"));
+        assert!(!Lexer::is_keyword("name"));
+    }
+
+    #[test]
+    fn read_keywords_in_any_case() {
+        let source = "Print len(\"x\") AND True\nfor i = 1 To 3 step -1\n";
+
+        assert_eq!(tokenize_in(Dialect::CaseInsensitive, source), Ok(vec![
+            Token::Print,
+            identifier("LEN"),
+            Token::LeftParen,
+            Token::String("x".to_string(), STRING_ID),
+            Token::RightParen,
+            Token::And,
+            Token::Bool(true, BOOL_ID),
+            Token::Newline,
+            Token::For,
+            identifier("i"),
+            Token::Assign,
+            Token::Number(1, NUMBER_ID),
+            Token::To,
+            Token::Number(3, NUMBER_ID),
+            Token::Step,
+            Token::Number(-1, NUMBER_ID),
+            Token::Newline,
+        ]));
+        assert_eq!(tokenize_in(Dialect::CaseInsensitive, "let Name = name\n"), Ok(vec![
+            Token::Let,
+            identifier("Name"),
+            Token::Assign,
+            identifier("name"),
+            Token::Newline,
+        ]));
+    }
+
+    #[test]
+    fn number_every_classic_line() {
+        let source = "10 let x = 1 REM the start\n\n20 print x rem done\n";
+
+        assert_eq!(tokenize_in(Dialect::Classic, source), Ok(vec![
+            Token::Label(10),
+            Token::Let,
+            identifier("x"),
+            Token::Assign,
+            Token::Number(1, NUMBER_ID),
+            Token::Newline,
+            Token::Newline,
+            Token::Label(20),
+            Token::Print,
+            identifier("x"),
+            Token::Newline,
+        ]));
+        assert_eq!(tokenize_in(Dialect::Classic, "10 PRINT 1\nPRINT 2\n5 PRINT 3\n10 PRINT 4\n"), Err(vec![
+            "a classic program starts every line with its number".to_string(),
+            "line numbers go up, `5` comes after `10`".to_string(),
+            "line numbers go up, `10` comes after `10`".to_string(),
+        ]));
+    }
+
+    #[test]
+    fn pick_the_dialect_of_the_file() {
+        assert_eq!(tokenize_in(Dialect::Strict, "#!dialect case-insensitive\nprint 1\n"), Ok(vec![
+            Token::Newline,
+            Token::Print,
+            Token::Number(1, NUMBER_ID),
+            Token::Newline,
+        ]));
+        assert_eq!(tokenize_in(Dialect::Classic, "#!dialect strict\nprint 1\n"), Ok(vec![
+            Token::Newline,
+            identifier("print"),
+            Token::Number(1, NUMBER_ID),
+            Token::Newline,
+        ]));
+        assert_eq!(tokenize_in(Dialect::Strict, "PRINT 1\n#!dialect classic\n"), Ok(vec![
+            Token::Print,
+            Token::Number(1, NUMBER_ID),
+            Token::Newline,
+            Token::Newline,
+        ]));
+
+        let error = self::Lexer::new().tokenize("#!dialect  basic \n".to_string()).unwrap_err();
+
+        assert_eq!(error[0].message, "unknown dialect `basic`, expected strict, case-insensitive or classic");
+        assert_eq!(error[0].span, Span::new(11, 1, 12, 5));
+    }
+
+    #[test]
+    fn reject_keywords_as_names() {
+        let source = "LET TO = 1\nDIM NEXT(3)\nSUB f(a, STEP)\nENDSUB\nFOR true = 1 TO 2\nNEXT THEN\nINPUT \"?\" PRINT\nLET LEN = 1\nLET x = NOT y\n";
+        let result = self::Lexer::new().tokenize(source.to_string()).unwrap_err();
+        let errors: Vec<(usize, String)> = result.into_iter().map(|error| (error.span.line, error.message)).collect();
+
+        assert_eq!(errors, vec![
+            (1, "`TO` is a keyword, pick another name".to_string()),
+            (2, "`NEXT` is a keyword, pick another name".to_string()),
+            (3, "`STEP` is a keyword, pick another name".to_string()),
+            (5, "`true` is a keyword, pick another name".to_string()),
+            (6, "`THEN` is a keyword, pick another name".to_string()),
+            (7, "`PRINT` is a keyword, pick another name".to_string()),
+        ]);
+        assert_eq!(tokenize_in(Dialect::CaseInsensitive, "LET print = 1\n"), Err(vec!["`PRINT` is a keyword, pick another name".to_string()]));
+        assert!(tokenize_in(Dialect::Strict, "LET print = 1\nLET fn = 2\n").is_ok());
+    }
+
+    #[test]
+    fn keep_the_names_of_the_generated_code_apart() {
+        let source = "LET primal_len = LEN(s)\nSUB primal_checked(a)\nENDSUB\nFOR i = 1 TO 2\nLET primal_value = i\nNEXT\n10 LET primal_pc = 3\nGOTO 10\nLET primal_self = self\n";
+        let result = self::Lexer::new().tokenize(source.to_string()).unwrap_err();
+        let errors: Vec<(usize, usize)> = result.iter().map(|error| (error.span.line, error.span.column)).collect();
+
+        assert_eq!(errors, vec![(1, 5), (2, 5), (5, 5), (7, 8), (9, 5)]);
+        assert_eq!(
+            result[0].message,
+            "`primal_len` starts with `primal_`, which the generated code keeps for itself, pick another name"
+        );
+        assert!(tokenize_in(Dialect::Strict, "LET primal = 1\nLET Primal_x = primalx\n").is_ok());
+    }
+}
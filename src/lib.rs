@@ -1,5 +1,10 @@
+pub mod bigint;
+pub mod builtins;
+pub mod diagnostic;
 pub mod lexer;
 pub mod parser;
 pub mod emitter;
 pub mod optimizer;
-
+pub mod semantic;
+pub mod interpreter;
+pub mod strings;
@@ -1,30 +1,147 @@
-use std::{env, fs};
-use primal::{emitter, lexer, parser, optimizer};
+use std::{env, fs, io, process, thread};
+use primal::{emitter, interpreter, lexer, optimizer, parser, semantic};
+use primal::diagnostic::{Diagnostic, Span};
+use primal::lexer::Dialect;
+use primal::parser::Statement;
+use primal::semantic::{NumberMode, Symbols};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+#[derive(PartialEq)]
+enum Command {
+    // write Rust code for primal-runner
+    Compile,
+    // execute right away with the interpreter
+    Run,
+}
+
+struct Options {
+    command: Command,
+    file_path: String,
+    // 0 leaves the program as written
+    opt_level: u8,
+    numbers: NumberMode,
+    // a `#!dialect` pragma in the file goes first
+    dialect: Dialect,
+}
+
+const USAGE: &str = "usage: primal [run] [--opt-level=<0-2>] [--bigint] [--dialect=<strict|case-insensitive|classic>] <file.roq>";
 
-    let file_path = &args[1];
+fn main() {
+    let options = Options::from_args(env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, USAGE);
+        process::exit(2);
+    });
     
-    let file_contents = fs::read_to_string(file_path)
-        .expect("Should have been able to read a file");
+    let file_contents = fs::read_to_string(&options.file_path).unwrap_or_else(|error| {
+        let diagnostic = Diagnostic::error(format!("cannot read the file: {}", error), Span::default());
+        report_and_exit(&[diagnostic], &options.file_path, "");
+    });
+
+    let (ast, symbols) = build_ast(&options, &file_contents);
+
+    match options.command {
+        Command::Compile => {
+            let mut emitter = emitter::Emitter::new(ast, symbols);
+            let lines = emitter.emit();
+
+            fs::write("./primal-runner/src/main.rs", lines.join("\n")).expect("Writing lines to output rust file");
+            println!("Wrote to file");
+        }
+        Command::Run => {
+            // every primal call is a few native ones deep
+            let result = thread::Builder::new()
+                .stack_size(interpreter::STACK_SIZE)
+                .spawn(move || {
+                    interpreter::Interpreter::new(symbols, io::stdin().lock(), io::stdout().lock()).run(&ast)
+                })
+                .expect("Should have been able to start the interpreter")
+                .join()
+                .expect("The interpreter should not have panicked");
+
+            if let Err(diagnostic) = result {
+                report_and_exit(&[diagnostic], &options.file_path, &file_contents);
+            }
+        }
+    }
+}
+
+fn build_ast(options: &Options, file_contents: &str) -> (Vec<Statement>, Symbols) {
+    let file_path = &options.file_path;
+    // keep the program output clean when running it
+    let is_verbose = options.command == Command::Compile;
 
-    let tokens = lexer::Lexer::tokenize(file_contents);
-    println!("Tokens are ready!");
+    let tokens = lexer::Lexer::with_dialect(options.dialect).tokenize(file_contents.to_string())
+        .unwrap_or_else(|diagnostics| report_and_exit(&diagnostics, file_path, file_contents));
+    if is_verbose { println!("Tokens are ready!"); }
 
     let mut parser = parser::Parser::new(tokens);
     // an Abstract Syntax Tree
-    let ast = parser.parse();
-    println!("Abstract Syntax Tree has been constructed!");
+    let ast = parser.parse()
+        .unwrap_or_else(|diagnostics| report_and_exit(&diagnostics, file_path, file_contents));
+    if is_verbose { println!("Abstract Syntax Tree has been constructed!"); }
 
-    // dbg!(&ast);
-    // let mut optimizer = optimizer::Optimizer::new();
-    // let ast = optimizer.optimize(ast);
+    let symbols = semantic::Analyzer::with_numbers(options.numbers).analyze(&ast)
+        .unwrap_or_else(|diagnostics| report_and_exit(&diagnostics, file_path, file_contents));
+    if is_verbose { println!("Types check out!"); }
 
-    let mut emitter = emitter::Emitter::new(ast);
-    let lines = emitter.emit();
+    let mut optimizer = optimizer::Optimizer::new(options.opt_level, symbols.clone());
+    let ast = optimizer.optimize(ast);
+    report(&optimizer.take_warnings(), file_path, file_contents);
+    if is_verbose && options.opt_level > 0 { println!("Optimized at level {}!", options.opt_level); }
 
-    fs::write("./primal-runner/src/main.rs", lines.join("\n")).expect("Writing lines to output rust file");
-    println!("Wrote to file");
+    (ast, symbols)
 }
 
+fn report(diagnostics: &[Diagnostic], file_path: &str, source: &str) {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(file_path, source));
+    }
+}
+
+fn report_and_exit(diagnostics: &[Diagnostic], file_path: &str, source: &str) -> ! {
+    report(diagnostics, file_path, source);
+    process::exit(1);
+}
+
+impl Options {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut command = Command::Compile;
+        let mut file_path = None;
+        let mut opt_level = 0;
+        let mut numbers = NumberMode::Fixed;
+        let mut dialect = Dialect::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "run" if file_path.is_none() && command == Command::Compile => command = Command::Run,
+                // both "--opt-level=2" and "--opt-level 2"
+                "--opt-level" => opt_level = Self::opt_level(args.next().as_deref())?,
+                _ if arg.starts_with("--opt-level=") => opt_level = Self::opt_level(arg.split_once('=').map(|(_, level)| level))?,
+                "--bigint" => numbers = NumberMode::Big,
+                "--dialect" => dialect = Self::dialect(args.next().as_deref())?,
+                _ if arg.starts_with("--dialect=") => dialect = Self::dialect(arg.split_once('=').map(|(_, name)| name))?,
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ if file_path.is_none() => file_path = Some(arg),
+                _ => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+
+        let file_path = file_path.ok_or("missing the .roq file to work on")?;
+
+        Ok(Options { command, file_path, opt_level, numbers, dialect })
+    }
+
+    fn opt_level(level: Option<&str>) -> Result<u8, String> {
+        let level = level.ok_or("missing the level for `--opt-level`")?;
+
+        match level.parse() {
+            Ok(level) if level <= optimizer::MAX_OPT_LEVEL => Ok(level),
+            _ => Err(format!("invalid optimization level `{}`, expected 0 to {}", level, optimizer::MAX_OPT_LEVEL)),
+        }
+    }
+
+    fn dialect(name: Option<&str>) -> Result<Dialect, String> {
+        let name = name.ok_or("missing the dialect for `--dialect`")?;
+
+        Dialect::find(name).ok_or(format!("unknown dialect `{}`, expected strict, case-insensitive or classic", name))
+    }
+}
@@ -1,27 +1,864 @@
-use crate::parser::Statement;
-
-    // FOR LATER
-    //
-    // analyze key points:
-    // - follow identifiers (check types; if static, save as a number)
-    // - if/while statements check for easier solutions, e.g. while x == true OR x > 1
-    // - cache computed results
-    // READ the wiki page about optimizing compiler
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::{Token, BOOL_ID, NUMBER_ID, STRING_ID};
+use crate::parser::{Condition, Expression, Statement};
+use crate::semantic::{Symbols, Type};
 
+/// Rewrites the Abstract Syntax Tree into an equivalent but cheaper one.
+///
+/// - level 0 leaves the program as it is
+/// - level 1 folds constant expressions and conditions, `2 * 3 + x - x` becomes `6`,
+///   and drops code which never runs
+/// - level 2 also propagates variables which are set only once, from a literal
+///
+/// Overflows which cancel out are gone after folding, a division by zero or a call never is.
+/// Float calculations are left as written, rounding makes `f + 1 - f` differ from `1`.
 pub struct Optimizer {
+    level: u8,
+    symbols: Symbols,
+    // the SUB being optimized, None for main
+    scope: Option<String>,
+    // variables assigned exactly once in the main program, or in the SUB being optimized
+    single_assignments: Vec<String>,
+    // variables known to hold the same literal wherever they are used
+    constants: HashMap<String, Token>,
+    warnings: Vec<Diagnostic>,
 }
 
+pub const MAX_OPT_LEVEL: u8 = 2;
+
 impl Optimizer {
-    pub fn new() -> Self {
-    // hack into NASA
-    // download the secret optimisation code
-    // run it all at the same time no questions asked
-    // spit it out
-        Optimizer {}
+    pub fn new(level: u8, symbols: Symbols) -> Self {
+        Optimizer {
+            level,
+            symbols,
+            scope: None,
+            single_assignments: Vec::new(),
+            constants: HashMap::new(),
+            warnings: Vec::new(),
+        }
     }
 
     pub fn optimize(&mut self, ast: Vec<Statement>) -> Vec<Statement> {
-        ast
+        if self.level == 0 {
+            return ast;
+        }
+
+        // with jumps, what comes first in the file does not have to run first
+        if !Self::jumps(&ast) {
+            let mut assignments = HashMap::new();
+            Self::count_assignments(&ast, &mut assignments);
+            self.find_single_assignments(assignments);
+        }
+
+        ast.into_iter()
+            .flat_map(|statement| self.optimize_statement(statement, true))
+            .collect()
+    }
+
+    /// Suspicious code found while optimizing, e.g. a loop which never ends.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn find_single_assignments(&mut self, assignments: HashMap<String, usize>) {
+        if self.level < 2 {
+            return;
+        }
+
+        self.single_assignments = assignments
+            .into_iter()
+            .filter(|(_, count)| *count == 1)
+            .map(|(name, _)| name)
+            .collect();
+    }
+
+    fn jumps(statements: &[Statement]) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Goto { .. } | Statement::Gosub { .. } | Statement::GosubReturn { .. } => true,
+            Statement::If { if_body, elseif_branches, else_body, .. } => {
+                Self::jumps(if_body)
+                    || elseif_branches.iter().any(|(_, body)| Self::jumps(body))
+                    || Self::jumps(else_body)
+            }
+            Statement::While { while_body: body, .. } | Statement::For { for_body: body, .. } => Self::jumps(body),
+            _ => false,
+        })
+    }
+
+    // in the current scope only, SUBs have variables of their own
+    fn count_assignments(statements: &[Statement], assignments: &mut HashMap<String, usize>) {
+        for statement in statements {
+            match statement {
+                Statement::Let { identifier: Token::Identifier(name, _), .. }
+                | Statement::Input { identifier: Token::Identifier(name, _), .. } => {
+                    *assignments.entry(name.to_string()).or_insert(0) += 1;
+                }
+                Statement::If { if_body, elseif_branches, else_body, .. } => {
+                    Self::count_assignments(if_body, assignments);
+
+                    for (_, body) in elseif_branches {
+                        Self::count_assignments(body, assignments);
+                    }
+                    Self::count_assignments(else_body, assignments);
+                }
+                Statement::While { while_body, .. } => Self::count_assignments(while_body, assignments),
+                Statement::For { identifier: Token::Identifier(name, _), for_body, .. } => {
+                    *assignments.entry(name.to_string()).or_insert(0) += 1;
+                    Self::count_assignments(for_body, assignments);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // none, one, or the inlined body of a block which always runs
+    fn optimize_statement(&mut self, statement: Statement, is_top_level: bool) -> Vec<Statement> {
+        match statement {
+            Statement::Print { items } => vec![Statement::Print {
+                items: items
+                    .into_iter()
+                    .map(|(expression, separator)| (self.fold_expression(expression), separator))
+                    .collect(),
+            }],
+            Statement::Let { identifier, expression_tree } => {
+                let expression_tree = self.fold_expression(expression_tree);
+
+                // a top level LET runs before anything that comes after it in the file
+                if let (Token::Identifier(name, _), Expression::Value { value, .. }) = (&identifier, &expression_tree) {
+                    let is_literal = !matches!(value, Token::Identifier(..));
+
+                    if is_literal && is_top_level && self.single_assignments.contains(name) {
+                        self.constants.insert(name.to_string(), value.clone());
+                    }
+                }
+
+                vec![Statement::Let { identifier, expression_tree }]
+            }
+            Statement::If { condition_tree, if_body, elseif_branches, else_body } => {
+                let mut branches = vec![];
+                // runs when none of the remaining branches does
+                let mut fallback = else_body;
+
+                for (condition, body) in std::iter::once((condition_tree, if_body)).chain(elseif_branches) {
+                    let condition = self.fold_condition(condition);
+
+                    match Self::as_bool(&condition) {
+                        Some(false) => {}
+                        // the branches after it never run
+                        Some(true) => {
+                            fallback = body;
+                            break;
+                        }
+                        None => branches.push((condition, body)),
+                    }
+                }
+
+                // runs whenever the code around it does
+                if branches.is_empty() {
+                    return fallback
+                        .into_iter()
+                        .flat_map(|statement| self.optimize_statement(statement, is_top_level))
+                        .collect();
+                }
+
+                let mut branches: Vec<(Condition, Vec<Statement>)> = branches
+                    .into_iter()
+                    .map(|(condition, body)| (condition, self.optimize_block(body)))
+                    .collect();
+                let else_body = self.optimize_block(fallback);
+
+                let is_empty = else_body.is_empty() && branches
+                    .iter()
+                    .all(|(condition, body)| body.is_empty() && !Self::condition_has_effects(condition));
+
+                if is_empty {
+                    return vec![];
+                }
+
+                let (condition_tree, if_body) = branches.remove(0);
+                vec![Statement::If { condition_tree, if_body, elseif_branches: branches, else_body }]
+            }
+            Statement::While { condition_tree, while_body } => {
+                let condition_tree = self.fold_condition(condition_tree);
+
+                if Self::as_bool(&condition_tree) == Some(false) {
+                    return vec![];
+                }
+
+                let while_body = self.optimize_block(while_body);
+
+                if Self::as_bool(&condition_tree) == Some(true) && !Self::breaks(&while_body, true) {
+                    self.warnings.push(Diagnostic::warning(
+                        "this loop never ends, its condition is always true and it has no BREAK".to_string(),
+                        condition_tree.span(),
+                    ));
+                }
+
+                vec![Statement::While { condition_tree, while_body }]
+            }
+            Statement::For { identifier, start, end, step, for_body, span } => vec![Statement::For {
+                identifier,
+                start: self.fold_expression(start),
+                end: self.fold_expression(end),
+                step: step.map(|step| self.fold_expression(step)),
+                for_body: self.optimize_block(for_body),
+                span,
+            }],
+            Statement::Sub { name, parameters, sub_body } => {
+                // parameters are set by every call
+                let mut assignments: HashMap<String, usize> = parameters
+                    .iter()
+                    .map(|parameter| (parameter.to_string(), 1))
+                    .collect();
+                Self::count_assignments(&sub_body, &mut assignments);
+
+                let outer_assignments = std::mem::take(&mut self.single_assignments);
+                let outer_constants = std::mem::take(&mut self.constants);
+                self.find_single_assignments(assignments);
+
+                self.scope = Some(name.to_string());
+                let sub_body = self.optimize_statements(sub_body, true);
+                self.scope = None;
+
+                self.single_assignments = outer_assignments;
+                self.constants = outer_constants;
+
+                vec![Statement::Sub { name, parameters, sub_body }]
+            }
+            Statement::Dim { identifier, size } => vec![Statement::Dim { identifier, size: self.fold_expression(size) }],
+            Statement::LetElement { identifier, index, expression_tree } => vec![Statement::LetElement {
+                identifier,
+                index: self.fold_expression(index),
+                expression_tree: self.fold_expression(expression_tree),
+            }],
+            Statement::Call { name, arguments, span } => vec![Statement::Call {
+                name,
+                arguments: arguments.into_iter().map(|argument| self.fold_expression(argument)).collect(),
+                span,
+            }],
+            Statement::Return { value, span } => vec![Statement::Return {
+                value: value.map(|value| self.fold_expression(value)),
+                span,
+            }],
+            Statement::Empty => vec![],
+            statement => vec![statement],
+        }
+    }
+
+    fn optimize_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+        self.optimize_statements(statements, false)
+    }
+
+    fn optimize_statements(&mut self, statements: Vec<Statement>, is_top_level: bool) -> Vec<Statement> {
+        let mut optimized = vec![];
+
+        for statement in statements {
+            optimized.extend(self.optimize_statement(statement, is_top_level));
+
+            // nothing after a jump ever runs, blocks have no line numbers to jump back in
+            let is_jump = matches!(
+                optimized.last(),
+                Some(Statement::Break | Statement::Continue | Statement::Return { .. } | Statement::Goto { .. } | Statement::GosubReturn { .. })
+            );
+
+            if is_jump {
+                break;
+            }
+        }
+        optimized
+    }
+
+    // whether a BREAK, RETURN or GOTO leaves this loop, a BREAK in a nested loop only leaves that one
+    fn breaks(statements: &[Statement], is_innermost: bool) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Break => is_innermost,
+            Statement::Return { .. } | Statement::Goto { .. } | Statement::GosubReturn { .. } => true,
+            Statement::If { if_body, elseif_branches, else_body, .. } => {
+                Self::breaks(if_body, is_innermost)
+                    || elseif_branches.iter().any(|(_, body)| Self::breaks(body, is_innermost))
+                    || Self::breaks(else_body, is_innermost)
+            }
+            Statement::While { while_body: body, .. } | Statement::For { for_body: body, .. } => Self::breaks(body, false),
+            _ => false,
+        })
+    }
+
+    fn fold_expression(&self, expression: Expression) -> Expression {
+        match expression {
+            Expression::Value { value: Token::Identifier(name, _), span } if self.constants.contains_key(&name) => {
+                Expression::Value { value: self.constants[&name].clone(), span }
+            }
+            Expression::Value { .. } => expression,
+            Expression::Unary { operator, operand, span } => {
+                let operand = self.fold_expression(*operand);
+
+                match (operator, operand) {
+                    (Token::Plus, operand) => operand,
+                    (Token::Minus, Expression::Value { value: Token::Number(value, _), span: operand_span }) => {
+                        match value.checked_neg() {
+                            Some(negated) => Self::number(negated, span),
+                            None => Expression::Unary { operator: Token::Minus, operand: Box::new(Self::number(value, operand_span)), span },
+                        }
+                    }
+                    (Token::Minus, Expression::Unary { operator: Token::Minus, operand, .. }) => *operand,
+                    (operator, operand) => Expression::Unary { operator, operand: Box::new(operand), span },
+                }
+            }
+            Expression::Binary { left, operator, right, span } => {
+                let left = self.fold_expression(*left);
+                let right = self.fold_expression(*right);
+
+                self.simplify(left, operator, right, span)
+            }
+            Expression::Call { name, arguments, span } => Expression::Call {
+                name,
+                arguments: arguments.into_iter().map(|argument| self.fold_expression(argument)).collect(),
+                span,
+            },
+            Expression::Element { identifier, index, span } => Expression::Element {
+                identifier,
+                index: Box::new(self.fold_expression(*index)),
+                span,
+            },
+        }
+    }
+
+    // both operands are folded already
+    fn simplify(&self, left: Expression, operator: Token, right: Expression, span: Span) -> Expression {
+        if let (Expression::Value { value: Token::String(left, _), .. }, Token::Plus, Expression::Value { value: Token::String(right, _), .. }) =
+            (&left, &operator, &right)
+        {
+            return Expression::Value { value: Token::String(left.to_owned() + right, STRING_ID), span };
+        }
+
+        let left_number = Self::as_number(&left);
+        let right_number = Self::as_number(&right);
+
+        if let (Some(left), Some(right)) = (left_number, right_number) {
+            if let Some(result) = Self::calculate(left, &operator, right) {
+                return Self::number(result, span);
+            }
+        }
+
+        match (&operator, left_number, right_number) {
+            (Token::Plus | Token::Minus, ..) => {
+                let binary = Expression::Binary { left: Box::new(left), operator, right: Box::new(right), span };
+
+                self.simplify_sum(binary)
+            }
+            (Token::Times, Some(1), _) => right,
+            (Token::Times, _, Some(1)) | (Token::Divide, _, Some(1)) => left,
+            // a Float may be infinite or NaN, or have a fraction
+            (Token::Times, Some(0), _) if self.is_integer(&right) && !Self::has_effects(&right) => Self::number(0, span),
+            (Token::Times, _, Some(0)) | (Token::Modulo, _, Some(1)) if self.is_integer(&left) && !Self::has_effects(&left) => {
+                Self::number(0, span)
+            }
+            _ => Expression::Binary { left: Box::new(left), operator, right: Box::new(right), span },
+        }
+    }
+
+    // adds up the constants of a chain of + and -, and cancels out variables like in "a + x - x"
+    fn simplify_sum(&self, sum: Expression) -> Expression {
+        let span = sum.span();
+
+        if !self.is_integer(&sum) {
+            return sum;
+        }
+
+        let mut terms = vec![];
+        Self::flatten_sum(&sum, false, &mut terms);
+
+        let mut constant: i32 = 0;
+        let mut variables: Vec<(bool, Expression)> = vec![];
+
+        for (is_negative, term) in terms {
+            let Some(number) = Self::as_number(term) else {
+                variables.push((is_negative, term.clone()));
+                continue;
+            };
+
+            let total = match is_negative {
+                true => constant.checked_sub(number),
+                false => constant.checked_add(number),
+            };
+
+            match total {
+                Some(total) => constant = total,
+                // leave it to the runtime to report
+                None => return sum,
+            }
+        }
+
+        // calls have to keep their order
+        if variables.len() > 1 && variables.iter().any(|(_, term)| Self::is_call(term)) {
+            return sum;
+        }
+
+        let mut index = 0;
+        while index < variables.len() {
+            let (is_negative, term) = &variables[index];
+            let opposite = variables
+                .iter()
+                .position(|(other_is_negative, other)| other_is_negative != is_negative && Self::is_same_variable(term, other));
+
+            match opposite {
+                Some(opposite) => {
+                    variables.remove(opposite.max(index));
+                    variables.remove(opposite.min(index));
+                }
+                None => index += 1,
+            }
+        }
+
+        // with more operands the new order could overflow where the old one did not
+        if variables.len() + usize::from(constant != 0) > 2 {
+            return sum;
+        }
+
+        let mut variables = variables.into_iter();
+
+        match (variables.next(), variables.next(), constant) {
+            (None, _, constant) => Self::number(constant, span),
+            (Some((false, term)), None, 0) => term,
+            (Some((true, term)), None, 0) => Expression::Unary { operator: Token::Minus, operand: Box::new(term), span },
+            (Some((false, term)), None, constant) if constant < 0 && constant != i32::MIN => {
+                Self::binary(term, Token::Minus, Self::number(-constant, span), span)
+            }
+            (Some((false, term)), None, constant) => Self::binary(term, Token::Plus, Self::number(constant, span), span),
+            (Some((true, term)), None, constant) => Self::binary(Self::number(constant, span), Token::Minus, term, span),
+            (Some((false, left)), Some((false, right)), _) => Self::binary(left, Token::Plus, right, span),
+            (Some((false, left)), Some((true, right)), _) => Self::binary(left, Token::Minus, right, span),
+            (Some((true, left)), Some((false, right)), _) => Self::binary(right, Token::Minus, left, span),
+            (Some((true, left)), Some((true, right)), _) => {
+                let left = Expression::Unary { operator: Token::Minus, operand: Box::new(left), span };
+
+                Self::binary(left, Token::Minus, right, span)
+            }
+        }
+    }
+
+    // terms of a sum, each one with its sign
+    fn flatten_sum<'a>(expression: &'a Expression, is_negative: bool, terms: &mut Vec<(bool, &'a Expression)>) {
+        match expression {
+            Expression::Binary { left, operator: Token::Plus, right, .. } => {
+                Self::flatten_sum(left, is_negative, terms);
+                Self::flatten_sum(right, is_negative, terms);
+            }
+            Expression::Binary { left, operator: Token::Minus, right, .. } => {
+                Self::flatten_sum(left, is_negative, terms);
+                Self::flatten_sum(right, !is_negative, terms);
+            }
+            Expression::Unary { operator: Token::Minus, operand, .. } => {
+                Self::flatten_sum(operand, !is_negative, terms);
+            }
+            _ => terms.push((is_negative, expression)),
+        }
+    }
+
+    fn is_call(expression: &Expression) -> bool {
+        matches!(expression, Expression::Call { .. })
+    }
+
+    fn fold_condition(&self, condition: Condition) -> Condition {
+        match condition {
+            Condition::Value { value: Token::Identifier(name, _), span } if self.constants.contains_key(&name) => {
+                Condition::Value { value: self.constants[&name].clone(), span }
+            }
+            Condition::Value { .. } => condition,
+            Condition::Comparison { left, operator, right, span } => {
+                let left = self.fold_expression(left);
+                let right = self.fold_expression(right);
+
+                let ordering = match (&left, &right) {
+                    // NaN is not even equal to itself
+                    (Expression::Value { .. }, _) if self.is_float(&left) => None,
+                    (Expression::Value { value: left, .. }, Expression::Value { value: right, .. }) => {
+                        Self::literal_ordering(left, right)
+                    }
+                    _ => None,
+                };
+
+                match ordering {
+                    Some(ordering) => Self::bool(Self::compare(ordering, &operator), span),
+                    None => Condition::Comparison { left, operator, right, span },
+                }
+            }
+            Condition::Not { operand, span } => match self.fold_condition(*operand) {
+                Condition::Value { value: Token::Bool(value, _), .. } => Self::bool(!value, span),
+                Condition::Not { operand, .. } => *operand,
+                operand => Condition::Not { operand: Box::new(operand), span },
+            },
+            Condition::Binary { left, operator, right, span } => {
+                let left = self.fold_condition(*left);
+                let right = self.fold_condition(*right);
+
+                // AND is true only if both are, OR if either is
+                let is_and = operator == Token::And;
+
+                match (Self::as_bool(&left), Self::as_bool(&right)) {
+                    (Some(value), _) if value != is_and => Self::bool(value, span),
+                    (Some(_), _) => right,
+                    (_, Some(value)) if value == is_and => left,
+                    (_, Some(value)) if !Self::condition_has_effects(&left) => Self::bool(value, span),
+                    _ => Condition::Binary { left: Box::new(left), operator, right: Box::new(right), span },
+                }
+            }
+        }
+    }
+
+    fn calculate(left: i32, operator: &Token, right: i32) -> Option<i32> {
+        match operator {
+            Token::Plus => left.checked_add(right),
+            Token::Minus => left.checked_sub(right),
+            Token::Times => left.checked_mul(right),
+            Token::Divide => left.checked_div(right),
+            Token::Modulo => left.checked_rem(right),
+            _ => None,
+        }
+    }
+
+    // only literals of the same type can be compared right away, and so can a variable with itself
+    fn literal_ordering(left: &Token, right: &Token) -> Option<Ordering> {
+        match (left, right) {
+            (Token::Number(left, _), Token::Number(right, _)) => Some(left.cmp(right)),
+            (Token::String(left, _), Token::String(right, _)) => Some(left.cmp(right)),
+            (Token::Bool(left, _), Token::Bool(right, _)) => Some(left.cmp(right)),
+            (Token::Identifier(left, _), Token::Identifier(right, _)) if left == right => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+
+    fn compare(ordering: Ordering, operator: &Token) -> bool {
+        match operator {
+            Token::Equals => ordering.is_eq(),
+            Token::NotEquals => ordering.is_ne(),
+            Token::MoreThan => ordering.is_gt(),
+            Token::MoreThanEquals => ordering.is_ge(),
+            Token::LessThan => ordering.is_lt(),
+            Token::LessThanEquals => ordering.is_le(),
+            _ => unreachable!("not a comparison operator: {:?}", operator),
+        }
+    }
+
+    // an expression can only be dropped if it cannot fail, nor run a SUB which may print
+    fn has_effects(expression: &Expression) -> bool {
+        match expression {
+            Expression::Value { .. } => false,
+            // out of bounds is a runtime error too
+            Expression::Call { .. } | Expression::Element { .. } => true,
+            Expression::Unary { operand, .. } => Self::has_effects(operand),
+            Expression::Binary { left, operator, right, .. } => {
+                let is_division = matches!(operator, Token::Divide | Token::Modulo);
+                let is_safe_divisor = matches!(Self::as_number(right), Some(divisor) if divisor != 0);
+
+                (is_division && !is_safe_divisor) || Self::has_effects(left) || Self::has_effects(right)
+            }
+        }
+    }
+
+    fn condition_has_effects(condition: &Condition) -> bool {
+        match condition {
+            Condition::Value { .. } => false,
+            Condition::Comparison { left, right, .. } => Self::has_effects(left) || Self::has_effects(right),
+            Condition::Not { operand, .. } => Self::condition_has_effects(operand),
+            Condition::Binary { left, right, .. } => {
+                Self::condition_has_effects(left) || Self::condition_has_effects(right)
+            }
+        }
+    }
+
+    fn is_same_variable(left: &Expression, right: &Expression) -> bool {
+        matches!(
+            (left, right),
+            (Expression::Value { value: Token::Identifier(left, _), .. }, Expression::Value { value: Token::Identifier(right, _), .. })
+            if left == right
+        )
+    }
+
+    fn is_integer(&self, expression: &Expression) -> bool {
+        self.symbols.type_of(expression, self.scope.as_deref()) == Some(Type::Number)
+    }
+
+    fn is_float(&self, expression: &Expression) -> bool {
+        self.symbols.type_of(expression, self.scope.as_deref()) == Some(Type::Float)
+    }
+
+    fn as_number(expression: &Expression) -> Option<i32> {
+        match expression {
+            Expression::Value { value: Token::Number(value, _), .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_bool(condition: &Condition) -> Option<bool> {
+        match condition {
+            Condition::Value { value: Token::Bool(value, _), .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn number(value: i32, span: Span) -> Expression {
+        Expression::Value { value: Token::Number(value, NUMBER_ID), span }
+    }
+
+    fn bool(value: bool, span: Span) -> Condition {
+        Condition::Value { value: Token::Bool(value, BOOL_ID), span }
+    }
+
+    fn binary(left: Expression, operator: Token, right: Expression, span: Span) -> Expression {
+        Expression::Binary { left: Box::new(left), operator, right: Box::new(right), span }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::{Lexer, FLOAT_ID, IDENTIFIER_ID};
+    use crate::parser::Parser;
+    use crate::semantic::Analyzer;
+    use super::*;
+
+    fn optimize(source: &str, level: u8) -> Vec<Statement> {
+        let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+
+        let symbols = Analyzer::new().analyze(&ast).unwrap();
+
+        Optimizer::new(level, symbols).optimize(ast)
+    }
+
+    fn let_expression(statement: &Statement) -> &Expression {
+        match statement {
+            Statement::Let { expression_tree, .. } => expression_tree,
+            _ => panic!("not a LET: {:?}", statement),
+        }
+    }
+
+    fn if_condition(statement: &Statement) -> &Condition {
+        match statement {
+            Statement::If { condition_tree, .. } => condition_tree,
+            _ => panic!("not an IF: {:?}", statement),
+        }
+    }
+
+    // what a PRINT of a single item prints, token by token
+    fn printed(statement: &Statement) -> Vec<Token> {
+        match statement {
+            Statement::Print { items } if items.len() == 1 => tokens(&items[0].0),
+            _ => panic!("not a PRINT of one item: {:?}", statement),
+        }
+    }
+
+    fn number(value: i32) -> Token {
+        Token::Number(value, NUMBER_ID)
+    }
+
+    fn identifier(name: &str) -> Token {
+        Token::Identifier(name.to_string(), IDENTIFIER_ID)
+    }
+
+    // the expression written out token by token, without parentheses
+    fn tokens(expression: &Expression) -> Vec<Token> {
+        match expression {
+            Expression::Value { value, .. } => vec![value.clone()],
+            Expression::Unary { operator, operand, .. } => [vec![operator.clone()], tokens(operand)].concat(),
+            Expression::Binary { left, operator, right, .. } => {
+                [tokens(left), vec![operator.clone()], tokens(right)].concat()
+            }
+            Expression::Call { name, arguments, .. } => {
+                let arguments = arguments.iter().map(tokens).collect::<Vec<_>>().join(&Token::Comma);
+
+                [vec![name.clone(), Token::LeftParen], arguments, vec![Token::RightParen]].concat()
+            }
+            Expression::Element { identifier, index, .. } => {
+                [vec![identifier.clone(), Token::LeftParen], tokens(index), vec![Token::RightParen]].concat()
+            }
+        }
+    }
+
+    #[test]
+    fn fold_arithmetic() {
+        let ast = optimize("LET x = 1\nLET y = 2 * 3 + x - x\nLET z = (x + 1) + 2 - 10\nLET w = x * 1 + 0 * x\n", 1);
+
+        assert_eq!(tokens(let_expression(&ast[1])), vec![number(6)]);
+        assert_eq!(tokens(let_expression(&ast[2])), vec![identifier("x"), Token::Minus, number(7)]);
+        assert_eq!(tokens(let_expression(&ast[3])), vec![identifier("x")]);
+    }
+
+    #[test]
+    fn span_the_folded_expression() {
+        let ast = optimize("LET x = 2 * 3 + 4\n", 1);
+
+        assert_eq!(let_expression(&ast[0]), &Expression::Value { value: number(10), span: Span::new(8, 1, 9, 9) });
+    }
+
+    #[test]
+    fn keep_what_fails_at_runtime() {
+        let ast = optimize("LET x = 1\nLET y = 1 / 0\nLET z = x / 0 * 0\nLET w = 2147483647 + 1\n", 1);
+
+        assert_eq!(tokens(let_expression(&ast[1])), vec![number(1), Token::Divide, number(0)]);
+        assert_eq!(tokens(let_expression(&ast[2])), vec![identifier("x"), Token::Divide, number(0), Token::Times, number(0)]);
+        assert_eq!(tokens(let_expression(&ast[3])), vec![number(2147483647), Token::Plus, number(1)]);
+    }
+
+    #[test]
+    fn fold_conditions() {
+        let source = "LET x = 1\nIF NOT (1 > 2) AND x == x THEN\nPRINT x\nENDIF\nIF x > 2 OR false THEN\nPRINT x\nENDIF\nIF \"a\" != \"a\" OR x / 0 == 1 THEN\nPRINT x\nENDIF\n";
+        let ast = optimize(source, 1);
+
+        assert!(matches!(&ast[1], Statement::Print { .. }));
+        assert!(matches!(if_condition(&ast[2]), Condition::Comparison { operator: Token::MoreThan, .. }));
+        assert!(matches!(if_condition(&ast[3]), Condition::Comparison { operator: Token::Equals, .. }));
+    }
+
+    #[test]
+    fn propagate_variables_set_once() {
+        let source = "LET a = 2\nLET b = a * 3\nPRINT b\nLET c = 1\nLET c = c + 1\nIF c > 1 THEN\nLET d = 5\nENDIF\nLET e = d\n";
+        let ast = optimize(source, 2);
+
+        assert_eq!(tokens(let_expression(&ast[1])), vec![number(6)]);
+        assert_eq!(printed(&ast[2]), vec![number(6)]);
+        assert_eq!(tokens(let_expression(&ast[4])), vec![identifier("c"), Token::Plus, number(1)]);
+        // the block might not run at all
+        assert_eq!(tokens(let_expression(&ast[6])), vec![identifier("d")]);
+    }
+
+    #[test]
+    fn remove_code_which_never_runs() {
+        let source = "LET x = 1\n\nIF 1 > 2 THEN\nPRINT x\nENDIF\nWHILE false\nDO\nPRINT x\nENDWHILE\nIF x > 0 THEN\nENDIF\nIF x / 0 > 0 THEN\nENDIF\n";
+        let ast = optimize(source, 1);
+
+        assert_eq!(ast.len(), 2);
+        // dividing by zero still has to fail
+        assert!(matches!(&ast[1], Statement::If { if_body, .. } if if_body.is_empty()));
+    }
+
+    #[test]
+    fn inline_blocks_which_always_run() {
+        let source = "LET x = 1\nIF x == x THEN\nLET y = 2\nIF true THEN\nPRINT y\nENDIF\nENDIF\nPRINT y\n";
+        let ast = optimize(source, 2);
+
+        assert_eq!(ast.len(), 4);
+        assert_eq!(printed(&ast[2]), vec![number(2)]);
+        assert_eq!(printed(&ast[3]), vec![number(2)]);
+    }
+
+    #[test]
+    fn keep_only_branches_which_may_run() {
+        let source = "LET x = 1\nIF false THEN\nPRINT 1\nELSEIF x > 1 THEN\nPRINT 2\nELSEIF true THEN\nPRINT 3\nELSE\nPRINT 4\nENDIF\nIF 1 > 2 THEN\nPRINT 5\nELSE\nPRINT 6\nENDIF\n";
+        let ast = optimize(source, 1);
+
+        assert_eq!(ast.len(), 3);
+        match &ast[1] {
+            Statement::If { condition_tree, if_body, elseif_branches, else_body } => {
+                assert!(matches!(condition_tree, Condition::Comparison { operator: Token::MoreThan, .. }));
+                assert_eq!(if_body.iter().map(printed).collect::<Vec<_>>(), vec![vec![number(2)]]);
+                assert!(elseif_branches.is_empty());
+                assert_eq!(else_body.iter().map(printed).collect::<Vec<_>>(), vec![vec![number(3)]]);
+            }
+            statement => panic!("not an IF: {:?}", statement),
+        }
+        assert_eq!(printed(&ast[2]), vec![number(6)]);
+    }
+
+    #[test]
+    fn warn_about_endless_loops() {
+        let source = "LET x = 1\nWHILE NOT (x > 1) OR true\nDO\nPRINT x\nENDWHILE\nWHILE true\nDO\nIF x > 1 THEN\nBREAK\nPRINT x\nENDIF\nENDWHILE\n";
+        let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+
+        let symbols = Analyzer::new().analyze(&ast).unwrap();
+
+        let mut optimizer = Optimizer::new(1, symbols);
+        let ast = optimizer.optimize(ast);
+        let warnings = optimizer.take_warnings();
+
+        assert_eq!(ast.len(), 3);
+        // the PRINT after BREAK is gone
+        assert!(matches!(&ast[2], Statement::While { while_body, .. } if matches!(&while_body[0], Statement::If { if_body, .. } if if_body.len() == 1)));
+        assert_eq!(warnings, vec![Diagnostic::warning(
+            "this loop never ends, its condition is always true and it has no BREAK".to_string(),
+            Span::new(16, 2, 7, 19),
+        )]);
+    }
+
+    #[test]
+    fn leave_endless_loops_from_nested_ones() {
+        let source = "10 WHILE 1 == 1\nDO\nFOR i = 1 TO 3\nGOTO 10\nNEXT\nENDWHILE\nWHILE true\nDO\nWHILE true\nDO\nBREAK\nENDWHILE\nENDWHILE\n";
+        let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+
+        let symbols = Analyzer::new().analyze(&ast).unwrap();
+
+        let mut optimizer = Optimizer::new(1, symbols);
+        optimizer.optimize(ast);
+
+        // only the outer loop around the BREAK never ends
+        assert_eq!(optimizer.take_warnings().iter().map(|warning| warning.span.line).collect::<Vec<_>>(), vec![7]);
+    }
+
+    #[test]
+    fn optimize_subs_on_their_own() {
+        let source = "LET x = 1\nSUB f(n)\nLET x = 2\nRETURN n * x\nPRINT n\nENDSUB\nLET z = 0 * f(x) - f(2)\nIF f(1) == 2 OR true THEN\nENDIF\n";
+        let ast = optimize(source, 2);
+
+        match &ast[1] {
+            Statement::Sub { sub_body, .. } => {
+                assert_eq!(sub_body.len(), 2);
+                assert!(matches!(
+                    &sub_body[1],
+                    Statement::Return { value: Some(value), .. } if tokens(value) == vec![identifier("n"), Token::Times, number(2)]
+                ));
+            }
+            statement => panic!("not a SUB: {:?}", statement),
+        }
+
+        // calls may print, so they stay where they are
+        assert_eq!(tokens(let_expression(&ast[2])), vec![
+            number(0), Token::Times, identifier("f"), Token::LeftParen, number(1), Token::RightParen,
+            Token::Minus, identifier("f"), Token::LeftParen, number(2), Token::RightParen,
+        ]);
+        assert!(matches!(&ast[3], Statement::If { .. }));
+    }
+
+    #[test]
+    fn leave_float_calculations_alone() {
+        let source = "INPUT \"?\" f\nLET x = f * 1.0\nLET y = f + 1 - f\nLET z = f * 0\nLET w = f % 1\nIF f == f THEN\nPRINT f\nENDIF\nLET n = 2 * 0.5 * 1\n";
+        let ast = optimize(source, 1);
+
+        assert_eq!(tokens(let_expression(&ast[2])), vec![identifier("f"), Token::Plus, number(1), Token::Minus, identifier("f")]);
+        assert_eq!(tokens(let_expression(&ast[3])), vec![identifier("f"), Token::Times, number(0)]);
+        assert_eq!(tokens(let_expression(&ast[4])), vec![identifier("f"), Token::Modulo, number(1)]);
+        // NaN == NaN is false
+        assert!(matches!(&ast[5], Statement::If { .. }));
+        assert_eq!(tokens(let_expression(&ast[6])), vec![number(2), Token::Times, Token::Float(0.5, FLOAT_ID)]);
+    }
+
+    #[test]
+    fn keep_variables_which_a_jump_may_skip() {
+        let ast = optimize("GOTO 10\nLET x = 1\n10 LET y = x + 1\n", 2);
+
+        assert_eq!(tokens(let_expression(&ast[3])), vec![identifier("x"), Token::Plus, number(1)]);
+    }
+
+    #[test]
+    fn join_string_literals() {
+        let source = "INPUT \"?\" name\nLET greeting = \"Hi \" + \"there, \" + name + \"!\"\nLET n = LEN(\"a\" + \"b\")\n";
+        let ast = optimize(source, 1);
+        let string = |text: &str| Token::String(text.to_string(), STRING_ID);
+
+        assert_eq!(tokens(let_expression(&ast[1])), vec![string("Hi there, "), Token::Plus, identifier("name"), Token::Plus, string("!")]);
+        assert_eq!(tokens(let_expression(&ast[2])), vec![identifier("LEN"), Token::LeftParen, string("ab"), Token::RightParen]);
+    }
+
+    #[test]
+    fn respect_the_level() {
+        let source = "LET a = 2\nLET b = a * 3\n";
+
+        assert_eq!(tokens(let_expression(&optimize(source, 0)[1])), vec![identifier("a"), Token::Times, number(3)]);
+        assert_eq!(tokens(let_expression(&optimize(source, 1)[1])), vec![identifier("a"), Token::Times, number(3)]);
+        assert_eq!(tokens(let_expression(&optimize(source, 2)[1])), vec![number(6)]);
+    }
+}
@@ -1,68 +1,124 @@
 use std::collections::HashMap;
-use crate::lexer::{Lexer, Token};
+use crate::builtins::Builtin;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::{Lexer, Spanned, Token};
 use crate::lexer::{
     BOOL_ID, 
     STRING_ID, 
     NUMBER_ID, 
     IDENTIFIER_ID};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Statement {
-    Print { value: Token },
+    // the separator after each item, one at the end keeps the line going
+    Print { items: Vec<(Expression, Option<Separator>)> },
     Let { identifier: Token, expression_tree: Expression },
-    If { condition_tree: Condition, if_body: Vec<Statement> },
+    // indices go from 0 up to the size, both included
+    Dim { identifier: Token, size: Expression },
+    LetElement { identifier: Token, index: Expression, expression_tree: Expression },
+    If {
+        condition_tree: Condition,
+        if_body: Vec<Statement>,
+        // tried in order when the condition is false
+        elseif_branches: Vec<(Condition, Vec<Statement>)>,
+        else_body: Vec<Statement>,
+    },
     While { condition_tree: Condition, while_body: Vec<Statement> },
-    Input { message: Token, identifier: Token },
+    // counts from start to end, both included; one at a time without a step
+    For {
+        identifier: Token,
+        start: Expression,
+        end: Expression,
+        step: Option<Expression>,
+        for_body: Vec<Statement>,
+        // of the header, for runtime errors about the step
+        span: Span,
+    },
+    // the span is for running out of input
+    Input { message: Token, identifier: Token, span: Span },
+    // leave the innermost loop, or go on with its next round
+    Break,
+    Continue,
+    // only at the top level, with variables of its own
+    Sub { name: Token, parameters: Vec<Token>, sub_body: Vec<Statement> },
+    Call { name: Token, arguments: Vec<Expression>, span: Span },
+    Return { value: Option<Expression>, span: Span },
+    // line numbers anywhere, jumps only to the ones outside of blocks and SUBs
+    Label { number: i32 },
+    Goto { label: i32, span: Span },
+    // in the main program outside of loops, so that RETURN can come back right after it
+    Gosub { label: i32, span: Span },
+    // a RETURN outside of SUBs goes back to the last GOSUB
+    GosubReturn { span: Span },
     Empty,
 }
 
 
-#[derive(PartialEq, Debug)]
-pub enum Operand {
-    Value { negation: bool, value: Token },
-    Operation { operation: Box<Operation> },
-}
-
-#[derive(PartialEq, Debug)]
-pub struct Operation {
-    operand_left: Option<Operand>,
-    operator: Option<Token>,
-    operand_right: Option<Operand>,
-}
-
-#[derive(PartialEq, Debug)]
-pub struct Condition {
-    operation: Operation,
+/// What comes after an item of a PRINT.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Separator {
+    /// `;`, the next item follows right away
+    Join,
+    /// `,`, the next item starts in the next tab zone
+    Zone,
 }
 
-#[derive(PartialEq, Debug)]
-pub enum Term {
-    Value { sign: Option<Token>, value: Token },
-    Operation { operation: Box<Expression> },
+/// How many characters a tab zone of PRINT has.
+pub const ZONE_WIDTH: usize = 14;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Condition {
+    // a lone identifier or bool
+    Value { value: Token, span: Span },
+    Comparison { left: Expression, operator: Token, right: Expression, span: Span },
+    Not { operand: Box<Condition>, span: Span },
+    // "AND" or "OR"
+    Binary { left: Box<Condition>, operator: Token, right: Box<Condition>, span: Span },
 }
 
-#[derive(PartialEq, Debug)]
-pub struct Expression {
-    left: Option<Term>,
-    numeric_operator: Option<Token>,
-    right: Option<Term>,
+#[derive(PartialEq, Debug, Clone)]
+pub enum Expression {
+    Value { value: Token, span: Span },
+    Unary { operator: Token, operand: Box<Expression>, span: Span },
+    Binary { left: Box<Expression>, operator: Token, right: Box<Expression>, span: Span },
+    // a SUB which returns a value
+    Call { name: Token, arguments: Vec<Expression>, span: Span },
+    Element { identifier: Token, index: Box<Expression>, span: Span },
 }
 
 #[derive(Debug)]
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<Spanned<Token>>,
     counter: usize,
     current: Option<Token>,
     next: Option<Token>,
     previous: Option<Token>,
+    // variables and arrays of the current scope, the main program or a SUB
     used_identifiers: HashMap<String, Token>,
+    arrays: Vec<String>,
+    // every variable set anywhere, to tell them apart from SUBs
+    declarations: Vec<(String, Span)>,
+    subs: Vec<String>,
+    // every line number, and whether it is outside of blocks and SUBs
+    labels: Vec<(i32, bool)>,
+    jumps: Vec<(i32, Span)>,
+    // how many blocks and loops the current statement is in
+    block_depth: usize,
+    loop_depth: usize,
+    is_in_sub: bool,
+    diagnostics: Vec<Diagnostic>,
 }
 
+type ParseResult<T> = Result<T, Diagnostic>;
+
+// what may end the body of an IF or of an ELSEIF
+const IF_TERMINATORS: [Token; 3] = [Token::Elseif, Token::Else, Token::Endif];
+
 const PLACEHOLDER: String = String::new();
 const BOOL_PLACEHOLDER: bool = false;
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Parser {
+    pub fn new(tokens: Vec<Spanned<Token>>) -> Parser {
         Parser {
             tokens,
             counter: 0,
@@ -70,10 +126,19 @@ impl Parser {
             next: None,
             previous: None,
             used_identifiers: HashMap::new(),
+            arrays: Vec::new(),
+            declarations: Vec::new(),
+            subs: Vec::new(),
+            labels: Vec::new(),
+            jumps: Vec::new(),
+            block_depth: 0,
+            loop_depth: 0,
+            is_in_sub: false,
+            diagnostics: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Statement> {
+    pub fn parse(&mut self) -> Result<Vec<Statement>, Vec<Diagnostic>> {
         // initialize current, next, counter
         self.next_token();
         self.next_token();
@@ -81,119 +146,385 @@ impl Parser {
         let mut statements: Vec<Statement> = vec![];
 
         while self.next.is_some() && self.counter <= self.tokens.len() {
-            statements.push(self.recognize_statement());
+            if let Some(statement) = self.recover(|parser| parser.recognize_statement()) {
+                statements.push(statement);
+            }
         }
-        statements
+
+        // a SUB may be defined after a variable of the same name
+        for (name, span) in &self.declarations {
+            if self.subs.contains(name) {
+                let message = format!("`{}` is the name of a SUB, it cannot be a variable too", name);
+                self.diagnostics.push(Diagnostic::error(message, *span));
+            }
+        }
+
+        // a jump may go to a line further down
+        for (label, span) in &self.jumps {
+            let message = match self.labels.iter().find(|(number, _)| number == label) {
+                Some((_, true)) => continue,
+                Some((_, false)) => format!("line `{}` is inside a block, jumps can only go to lines outside of them", label),
+                None => format!("there is no line `{}` to jump to", label),
+            };
+            self.diagnostics.push(Diagnostic::error(message, *span));
+        }
+
+        if !self.diagnostics.is_empty() {
+            self.diagnostics.sort_by_key(|diagnostic| diagnostic.span.offset);
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+        Ok(statements)
     }
 
     // recognize grammar-tree statement
-    fn recognize_statement(&mut self) -> Statement {
+    fn recognize_statement(&mut self) -> ParseResult<Statement> {
         match self.current {
             // "PRINT" value nl
+            // "PRINT" [expression {separator expression} [separator]] nl
             Some(Token::Print) => {
                 self.next_token();
 
-                self.value();
+                let mut items = vec![];
 
-                let value = self.previous.clone().unwrap();
+                while self.current != Some(Token::Newline) {
+                    let expression = self.expression()?;
+                    let separator = match self.current {
+                        Some(Token::Semicolon) => Some(Separator::Join),
+                        Some(Token::Comma) => Some(Separator::Zone),
+                        _ => None,
+                    };
 
-                self.newline();
+                    items.push((expression, separator));
 
-                Statement::Print { value }
+                    if separator.is_none() {
+                        break;
+                    }
+                    self.next_token();
+                }
+
+                self.newline()?;
+
+                Ok(Statement::Print { items })
             },
-            // "LET" identifier "=" value nl
+            // "LET" identifier "=" expression nl
             Some(Token::Let) => {
                 self.next_token();
 
-                self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID));
+                self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))?;
 
                 let identifier = self.previous.clone().unwrap();
 
-                self.add_identifier(identifier.clone());
+                // "LET" identifier "(" expression ")" "=" expression nl
+                if self.current == Some(Token::LeftParen) {
+                    self.check_array(&identifier)?;
+                    self.next_token();
+
+                    let index = self.expression()?;
 
-                self.match_token(Token::Assign);
+                    self.match_token(Token::RightParen)?;
+                    self.match_token(Token::Assign)?;
 
-                let expression;
-                let start;
+                    let expression_tree = self.expression()?;
 
-                if self.next == Some(Token::Newline) {
-                    self.value();
-                    expression = vec![self.previous.clone().unwrap()];
-                } else {
-                    start = self.counter;
-                    self.expression();
-                    expression = self.get_range_of_tokens(start, self.counter);
+                    self.newline()?;
+
+                    return Ok(Statement::LetElement { identifier, index, expression_tree });
                 }
-                let expression_tree = Self::expression_tree(&expression);
 
-                self.newline();
+                self.add_identifier(identifier.clone());
+
+                self.match_token(Token::Assign)?;
+
+                let expression_tree = self.expression()?;
+
+                self.newline()?;
                 
-                Statement::Let { identifier, expression_tree }
+                Ok(Statement::Let { identifier, expression_tree })
             },
-            // "IF" comparisons "THEN" nl {statement} nl "ENDIF" nl
+            // "IF" comparisons "THEN" nl {statement}
+            //     {"ELSEIF" comparisons "THEN" nl {statement}}
+            //     ["ELSE" nl {statement}]
+            // "ENDIF" nl
             Some(Token::If) => {
                 self.next_token();
 
-                let start = self.counter;
+                // a broken condition still lets us check the body
+                let condition_tree = self.recover(|parser| parser.if_header());
+                let if_body = self.block(&IF_TERMINATORS)?;
 
-                self.comparisons();
-                self.match_token(Token::Then);
+                let mut is_broken = condition_tree.is_none();
+                let mut elseif_branches = vec![];
 
-                let condition_range = self.get_range_of_tokens(start, self.counter - 1);
-                let condition_tree = Self::condition_tree(&condition_range);
+                while self.current == Some(Token::Elseif) {
+                    self.next_token();
 
-                self.match_token(Token::Newline);
+                    let condition = self.recover(|parser| parser.if_header());
+                    let body = self.block(&IF_TERMINATORS)?;
 
-                let mut statements: Vec<Statement> = Vec::new();
-                while self.current != Some(Token::Endif) {
-                    statements.push(self.recognize_statement());
+                    match condition {
+                        Some(condition) => elseif_branches.push((condition, body)),
+                        None => is_broken = true,
+                    }
                 }
 
-                self.match_token(Token::Endif);
-                self.newline();
+                let mut else_body = vec![];
+
+                if self.current == Some(Token::Else) {
+                    self.next_token();
 
-                Statement::If { condition_tree, if_body: statements }
+                    is_broken |= self.recover(|parser| parser.match_token(Token::Newline)).is_none();
+                    else_body = self.block(&[Token::Endif])?;
+                }
+
+                self.match_token(Token::Endif)?;
+                self.newline()?;
+
+                match (condition_tree, is_broken) {
+                    (Some(condition_tree), false) => Ok(Statement::If { condition_tree, if_body, elseif_branches, else_body }),
+                    _ => Ok(Statement::Empty),
+                }
             },
-            // "WHILE" comparisons nl "DO" nl {statement} nl "ENDWHILE" nl
+            // "WHILE" comparisons nl "DO" nl {statement} "ENDWHILE" nl
             Some(Token::While) => {
                 self.next_token();
 
-                let start = self.counter;
+                let condition_tree = self.recover(|parser| {
+                    let condition_tree = parser.comparisons()?;
+
+                    parser.newline()?;
+                    parser.match_token(Token::Do)?;
+                    parser.match_token(Token::Newline)?;
+
+                    Ok(condition_tree)
+                });
+
+                let statements = self.loop_body(Token::Endwhile)?;
+
+                self.match_token(Token::Endwhile)?;
+                self.newline()?;
+
+                match condition_tree {
+                    Some(condition_tree) => Ok(Statement::While { condition_tree, while_body: statements }),
+                    None => Ok(Statement::Empty),
+                }
+            },
+            // "FOR" identifier "=" expression "TO" expression ["STEP" expression] nl {statement} "NEXT" [identifier] nl
+            Some(Token::For) => {
+                let span = self.current_span();
+                self.next_token();
+
+                let header = self.recover(|parser| {
+                    parser.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))?;
+                    let identifier = parser.previous.clone().unwrap();
+
+                    parser.add_identifier(identifier.clone());
 
-                self.comparisons();
-                self.newline();
+                    parser.match_token(Token::Assign)?;
+                    let start = parser.expression()?;
 
-                let condition_range = self.get_range_of_tokens(start, self.counter - 1);
-                let condition_tree = Self::condition_tree(&condition_range);
+                    parser.match_token(Token::To)?;
+                    let end = parser.expression()?;
 
-                self.match_token(Token::Do);
+                    let mut step = None;
+                    if parser.current == Some(Token::Step) {
+                        parser.next_token();
+                        step = Some(parser.expression()?);
+                    }
 
-                let mut statements: Vec<Statement> = Vec::new();
-                self.match_token(Token::Newline);
-                while self.current != Some(Token::Endwhile) {
-                    statements.push(self.recognize_statement());
+                    let span = span.to(step.as_ref().unwrap_or(&end).span());
+                    parser.match_token(Token::Newline)?;
+
+                    Ok((identifier, start, end, step, span))
+                });
+
+                let for_body = self.loop_body(Token::Next)?;
+
+                self.match_token(Token::Next)?;
+
+                // "NEXT i" has to name the variable of its own loop
+                if let (Some(Token::Identifier(name, _)), Some((Token::Identifier(counter, _), ..))) = (&self.current, &header) {
+                    if name != counter {
+                        return Err(self.error(format!("`NEXT {}` does not close the loop over `{}`", name, counter)));
+                    }
+                    self.next_token();
                 }
 
-                self.match_token(Token::Endwhile);
-                self.newline();
+                self.newline()?;
 
-                Statement::While { condition_tree, while_body: statements }
+                match header {
+                    Some((identifier, start, end, step, span)) => Ok(Statement::For { identifier, start, end, step, for_body, span }),
+                    None => Ok(Statement::Empty),
+                }
             },
             // "INPUT" string identifier nl
             Some(Token::Input) => {
+                let span = self.current_span();
                 self.next_token();
 
-                self.match_token(Token::String(PLACEHOLDER, STRING_ID));
+                self.match_token(Token::String(PLACEHOLDER, STRING_ID))?;
                 let message = self.previous.clone().unwrap();
 
-                self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID));
+                self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))?;
                 let identifier = self.previous.clone().unwrap();
+                let span = span.to(self.previous_span());
                 
                 self.add_identifier(identifier.clone());
 
-                self.newline();
+                self.newline()?;
+
+                Ok(Statement::Input { message, identifier, span })
+            },
+            // "BREAK" nl | "CONTINUE" nl
+            Some(Token::Break) | Some(Token::Continue) => {
+                let keyword = self.current.clone().unwrap();
+
+                if self.loop_depth == 0 {
+                    return Err(self.error(format!("`{}` can only be used inside a loop", keyword)));
+                }
+
+                self.next_token();
+                self.newline()?;
+
+                match keyword {
+                    Token::Break => Ok(Statement::Break),
+                    _ => Ok(Statement::Continue),
+                }
+            },
+            // "SUB" identifier "(" [identifier {"," identifier}] ")" nl {statement} "ENDSUB" nl
+            Some(Token::Sub) => {
+                if self.block_depth > 0 {
+                    return Err(self.error("a SUB can only be defined at the top level".to_string()));
+                }
+                self.next_token();
+
+                // a SUB sees its parameters and its own variables, nothing else
+                let outer_identifiers = std::mem::take(&mut self.used_identifiers);
+                let outer_arrays = std::mem::take(&mut self.arrays);
+                let header = self.recover(|parser| parser.sub_header());
+
+                self.is_in_sub = true;
+                let sub_body = self.block(&[Token::Endsub]);
+                self.is_in_sub = false;
+
+                self.used_identifiers = outer_identifiers;
+                self.arrays = outer_arrays;
+                let sub_body = sub_body?;
+
+                self.match_token(Token::Endsub)?;
+                self.newline()?;
+
+                match header {
+                    Some((name, parameters)) => Ok(Statement::Sub { name, parameters, sub_body }),
+                    None => Ok(Statement::Empty),
+                }
+            },
+            // "CALL" call nl
+            Some(Token::Call) => {
+                self.next_token();
+
+                let (name, arguments, span) = self.call()?;
+
+                self.newline()?;
+
+                Ok(Statement::Call { name, arguments, span })
+            },
+            // "RETURN" [expression] nl
+            Some(Token::Return) => {
+                let span = self.current_span();
+                self.next_token();
+
+                let value = match self.current {
+                    Some(Token::Newline) => None,
+                    _ => Some(self.expression()?),
+                };
+                let span = value.as_ref().map_or(span, |value| span.to(value.span()));
+
+                // outside of SUBs it is the RETURN of classic BASIC, back to the last GOSUB
+                if !self.is_in_sub {
+                    if value.is_some() {
+                        return Err(Diagnostic::error("`RETURN` with a value can only be used inside a SUB".to_string(), span));
+                    }
+                    self.newline()?;
+
+                    return Ok(Statement::GosubReturn { span });
+                }
+
+                self.newline()?;
+
+                Ok(Statement::Return { value, span })
+            },
+            // "DIM" identifier "(" expression ")" nl
+            Some(Token::Dim) => {
+                self.next_token();
+
+                self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))?;
+
+                let identifier = self.previous.clone().unwrap();
+
+                if let Token::Identifier(array, _) = &identifier {
+                    self.check_builtin(array)?;
 
-                Statement::Input { message, identifier }
+                    if self.used_identifiers.contains_key(array) {
+                        return Err(Diagnostic::error(
+                            format!("`{}` is a variable, it cannot be an array too", array),
+                            self.previous_span(),
+                        ));
+                    }
+                    self.declarations.push((array.to_string(), self.previous_span()));
+
+                    if !self.arrays.contains(array) {
+                        self.arrays.push(array.to_string());
+                    }
+                }
+
+                self.match_token(Token::LeftParen)?;
+
+                let size = self.expression()?;
+
+                self.match_token(Token::RightParen)?;
+                self.newline()?;
+
+                Ok(Statement::Dim { identifier, size })
+            },
+            // "GOTO" number nl | "GOSUB" number nl
+            Some(Token::Goto) | Some(Token::Gosub) => {
+                let keyword = self.current.clone().unwrap();
+
+                if self.is_in_sub {
+                    return Err(self.error(format!("`{}` cannot jump out of a SUB", keyword)));
+                }
+                // RETURN can come back into the branch of an IF, but not into the middle of a loop
+                if keyword == Token::Gosub && self.loop_depth > 0 {
+                    return Err(self.error("`GOSUB` cannot be used inside a loop".to_string()));
+                }
+
+                self.next_token();
+                self.match_token(Token::Number(0, NUMBER_ID))?;
+
+                let label = match self.previous {
+                    Some(Token::Number(label, _)) => label,
+                    _ => unreachable!("matched a number"),
+                };
+                let span = self.previous_span();
+                self.jumps.push((label, span));
+
+                self.newline()?;
+
+                match keyword {
+                    Token::Gosub => Ok(Statement::Gosub { label, span }),
+                    _ => Ok(Statement::Goto { label, span }),
+                }
+            },
+            // label ::= number, in front of a statement
+            Some(Token::Label(number)) => {
+                if self.labels.iter().any(|(label, _)| *label == number) {
+                    return Err(self.error(format!("line number `{}` is used twice", number)));
+                }
+
+                self.labels.push((number, self.block_depth == 0));
+                self.next_token();
+
+                Ok(Statement::Label { number })
             },
             // nl ::= '\n'+
             Some(Token::Newline) => {
@@ -201,463 +532,926 @@ impl Parser {
 
                 // clear newlines till next different token
                 if self.current == Some(Token::Newline) {
-                    self.newline();
+                    self.newline()?;
                 }
 
-                Statement::Empty
+                Ok(Statement::Empty)
             },
+            // "print" in the strict dialect
+            Some(Token::Identifier(ref name, _)) if Lexer::is_keyword(&name.to_ascii_uppercase()) => Err(self.error(format!(
+                "expected a statement, found identifier `{}`, keywords are written in capitals like `{}` or the file needs `#!dialect case-insensitive`",
+                name,
+                name.to_ascii_uppercase(),
+            ))),
             _ => {
-                panic!("Syntax error: Token not recognized: {:#?}", self.current);
+                Err(self.error(format!("expected a statement, found {}", Self::describe(self.current.as_ref()))))
             }
         }
     }
 
-    fn condition_tree(condition: &[Token]) -> Condition {
-        let operation = Self::operation_tree(condition);
+    // comparisons "THEN" nl, after "IF" or "ELSEIF"
+    fn if_header(&mut self) -> ParseResult<Condition> {
+        let condition_tree = self.comparisons()?;
 
-        Condition { operation }
-    }
+        self.match_token(Token::Then)?;
+        self.match_token(Token::Newline)?;
 
-    fn operation_tree(condition: &[Token]) -> Operation {
-        let mut operation = Operation { 
-            operand_left: None,
-            operator: None,
-            operand_right: None,
-        };
-        let mut is_negation = false;
+        Ok(condition_tree)
+    }
 
-        for (index, token) in condition.iter().enumerate() {
-            let is_operand_and_left_is_empty = operation.operand_left.is_none() && Lexer::is_operand(token);
-            let is_operator_and_middle_is_empty = operation.operator.is_none() && Lexer::is_operator(token);
-            let is_operand_and_right_is_empty_and_is_last_token = operation.operand_right.is_none() && Lexer::is_operand(token) && index + 1 == condition.len();
-            let is_operator_and_right_is_empty = operation.operand_right.is_none() && Lexer::is_operator(token);
+    // identifier "(" [identifier {"," identifier}] ")" nl, after "SUB"
+    fn sub_header(&mut self) -> ParseResult<(Token, Vec<Token>)> {
+        self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))?;
+        let name = self.previous.clone().unwrap();
 
-            if *token == Token::Not {
-                is_negation = true;   
-            }
-            if is_operand_and_left_is_empty {
-                operation.operand_left = Some(Operand::Value { negation: is_negation, value: token.clone() });
-                is_negation = false;
-                continue; 
+        if let Token::Identifier(sub, _) = &name {
+            if sub == "main" {
+                return Err(Diagnostic::error("a SUB cannot be called `main`".to_string(), self.previous_span()));
             }
-            if is_operator_and_middle_is_empty {
-                operation.operator = Some(token.clone());
+            self.check_builtin(sub)?;
+            if self.subs.contains(sub) {
+                return Err(Diagnostic::error(format!("SUB `{}` is defined twice", sub), self.previous_span()));
             }
-            if is_operand_and_right_is_empty_and_is_last_token {
-                operation.operand_right = Some(Operand::Value { negation: is_negation, value: token.clone() });
-                is_negation = false;
-                continue;
+            self.subs.push(sub.to_string());
+        }
+
+        self.match_token(Token::LeftParen)?;
+
+        let mut parameters = vec![];
+
+        while self.current != Some(Token::RightParen) {
+            if !parameters.is_empty() {
+                self.match_token(Token::Comma)?;
             }
-            else if operation.operand_right.is_none() && Lexer::is_operator(token) {
-                // recursion here
-                operation.operand_right = 
-                    Some(Operand::Operation { operation:
-                        Box::new(Self::operation_tree(
-                            condition.get((index - 1)..condition.len())
-                            .unwrap()
-                        ))
-                    });
+            self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))?;
+
+            let parameter = self.previous.clone().unwrap();
+
+            if parameters.contains(&parameter) {
+                return Err(Diagnostic::error(format!("parameter `{}` is listed twice", parameter), self.previous_span()));
             }
+            self.add_identifier(parameter.clone());
+            parameters.push(parameter);
         }
-        operation
+
+        self.match_token(Token::RightParen)?;
+        self.match_token(Token::Newline)?;
+
+        Ok((name, parameters))
     }
 
-    fn expression_tree(expression: &[Token]) -> Expression {
-        let mut expression_tree = Expression {
-            left: None,
-            numeric_operator: None,
-            right: None,
-        };
+    // call ::= identifier "(" [expression {"," expression}] ")"
+    fn call(&mut self) -> ParseResult<(Token, Vec<Expression>, Span)> {
+        let span = self.current_span();
 
-        let mut signed_token = None;
+        self.match_token(Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))?;
+        let name = self.previous.clone().unwrap();
 
-        for (index, token) in expression.iter().enumerate() {
-            let is_sign_and_left_is_empty = expression_tree.left.is_none() && Lexer::is_sign(token);
-            let is_operand_and_left_is_empty = expression_tree.left.is_none() && Lexer::is_operand(token);
-            let is_numeric_and_numeric_field_is_empty = expression_tree.numeric_operator.is_none() && Lexer::is_numeric_operator(token);
-            let is_sign_and_right_is_empty = expression_tree.right.is_none() && Lexer::is_sign(token);
-            let is_operand_and_right_is_empty = expression_tree.right.is_none() && Lexer::is_operand(token);
-            let is_last_token = index == (expression.len() - 1);
+        self.match_token(Token::LeftParen)?;
 
-            // left
-            if is_sign_and_left_is_empty {
-                signed_token = Some(token);
-                continue;
-            }
-            if is_operand_and_left_is_empty {
-                expression_tree.left = Some(Term::Value { sign: signed_token.cloned(), value: token.clone() });
-                signed_token = None;
-                continue;
+        let mut arguments = vec![];
+
+        while self.current != Some(Token::RightParen) {
+            if !arguments.is_empty() {
+                self.match_token(Token::Comma)?;
             }
+            arguments.push(self.expression()?);
+        }
+
+        self.match_token(Token::RightParen)?;
+
+        Ok((name, arguments, span.to(self.previous_span())))
+    }
+
+    // {statement} up to one of the terminators, which is left for the caller;
+    // the last terminator is the one which closes the block for good
+    fn block(&mut self, terminators: &[Token]) -> ParseResult<Vec<Statement>> {
+        self.block_depth += 1;
+        let statements = self.block_statements(terminators);
+        self.block_depth -= 1;
+
+        statements
+    }
 
-            // middle
-            if is_numeric_and_numeric_field_is_empty {
-                expression_tree.numeric_operator = Some(token.clone());
-                continue;
+    fn block_statements(&mut self, terminators: &[Token]) -> ParseResult<Vec<Statement>> {
+        let mut statements: Vec<Statement> = Vec::new();
+
+        while !terminators.iter().any(|terminator| self.current.as_ref() == Some(terminator)) {
+            if self.current.is_none() {
+                return Err(self.error(format!(
+                    "expected {}, found {}",
+                    Self::describe(terminators.last()),
+                    Self::describe(None)
+                )));
             }
 
-            //right
-            if is_sign_and_right_is_empty {
-                signed_token = Some(token);
-                continue;
+            if let Some(statement) = self.recover(|parser| parser.recognize_statement()) {
+                statements.push(statement);
             }
-            if is_operand_and_right_is_empty && is_last_token {
-                expression_tree.right = Some(Term::Value { sign: signed_token.cloned(), value: token.clone() });
-                signed_token = None;
-                continue;
+        }
+        Ok(statements)
+    }
+
+    // the block of a WHILE or a FOR, where BREAK and CONTINUE make sense
+    fn loop_body(&mut self, terminator: Token) -> ParseResult<Vec<Statement>> {
+        self.loop_depth += 1;
+        let statements = self.block(&[terminator]);
+        self.loop_depth -= 1;
+
+        statements
+    }
+
+    // keep the diagnostic and move on to the next statement
+    fn recover<T>(&mut self, rule: impl FnOnce(&mut Self) -> ParseResult<T>) -> Option<T> {
+        match rule(self) {
+            Ok(result) => Some(result),
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                self.synchronize();
+                None
             }
-            // recursion here
-            expression_tree.right =
-                Some(Term::Operation { operation:
-                    Box::new(Self::expression_tree(
-                        expression.get((index - 1)..expression.len())
-                        .unwrap()
-                    ))
-                });
         }
+    }
+
+    // panic mode: skip to the end of the line or to the end of the block
+    fn synchronize(&mut self) {
+        let start = self.counter;
 
-        expression_tree
+        while !matches!(
+            self.current,
+            None | Some(Token::Newline)
+            | Some(Token::Else) | Some(Token::Elseif) | Some(Token::Endif) | Some(Token::Endwhile) | Some(Token::Next)
+            | Some(Token::Endsub)
+        ) {
+            self.next_token();
+        }
+
+        if self.current == Some(Token::Newline) {
+            while self.current == Some(Token::Newline) {
+                self.next_token();
+            }
+        } else if self.counter == start && self.current.is_some() {
+            // a block terminator nobody is waiting for, along with the rest of its line
+            self.next_token();
+            self.synchronize();
+        }
     }
 
-    fn check_identifier_from_string(&mut self, identifier: String) {
+    fn check_identifier_from_string(&self, identifier: String) -> ParseResult<()> {
+        if self.arrays.contains(&identifier) {
+            return Err(self.error(format!("`{0}` is an array, use one of its elements like `{0}(0)`", identifier)));
+        }
         if !self.used_identifiers.contains_key(&identifier) {
-            panic!("Compile error: using uninitialized variable {}", identifier);
+            return Err(self.error(format!("using uninitialized variable `{}`", identifier)));
+        }
+        Ok(())
+    }
+
+    // right after the identifier was matched
+    fn check_array(&self, identifier: &Token) -> ParseResult<()> {
+        match identifier {
+            Token::Identifier(array, _) if !self.arrays.contains(array) => Err(Diagnostic::error(
+                format!("array `{}` has to be set up with DIM first", array),
+                self.previous_span(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // right after the name was matched, the string functions keep theirs
+    fn check_builtin(&self, name: &str) -> ParseResult<()> {
+        match Builtin::find(name) {
+            Some(builtin) => Err(Diagnostic::error(
+                format!("`{}` is a string function, pick another name", builtin.name()),
+                self.previous_span(),
+            )),
+            None => Ok(()),
         }
     }
 
+    // right after the identifier was matched
     fn add_identifier(&mut self, identifier: Token) {
         if let Token::Identifier(ref variable, _) = identifier {
+            self.declarations.push((variable.to_string(), self.previous_span()));
+
+            if let Err(diagnostic) = self.check_builtin(variable) {
+                self.diagnostics.push(diagnostic);
+            }
+
+            if self.arrays.contains(variable) {
+                let message = format!("`{}` is an array, it cannot be a variable too", variable);
+                self.diagnostics.push(Diagnostic::error(message, self.previous_span()));
+            }
+
             if !self.used_identifiers.contains_key(variable) {
                 self.used_identifiers.insert(variable.to_string(), identifier);
             }
         }
     }
 
-    fn get_range_of_tokens(&mut self, start: usize, end: usize) -> Vec<Token> {
-        const SUBTRACT_FROM_COUNTER: usize = 2;
+    // comparisons ::= conjunction {"OR" conjunction}
+    fn comparisons(&mut self) -> ParseResult<Condition> {
+        let mut condition = self.conjunction()?;
+
+        while self.current == Some(Token::Or) {
+            let operator = self.current.clone().unwrap();
+            self.next_token();
 
-        self.tokens.get((start - SUBTRACT_FROM_COUNTER)..(end - SUBTRACT_FROM_COUNTER)).unwrap().to_vec()
+            let right = self.conjunction()?;
+            condition = Condition::binary(condition, operator, right);
+        }
+        Ok(condition)
     }
 
-    // comparisons ::= comparison {("AND" | "OR") comparison}
-    fn comparisons(&mut self) {
-        self.comparison();
+    // conjunction ::= negation {"AND" negation}
+    fn conjunction(&mut self) -> ParseResult<Condition> {
+        let mut condition = self.negation()?;
 
-        while self.current == Some(Token::And) || self.current == Some(Token::Or) {
+        while self.current == Some(Token::And) {
+            let operator = self.current.clone().unwrap();
             self.next_token();
 
-            self.comparison();
-        } 
+            let right = self.negation()?;
+            condition = Condition::binary(condition, operator, right);
+        }
+        Ok(condition)
     }
 
-    // comparison ::= (expression equals expression) | ("true" | "false")
-    fn comparison(&mut self) {
-        let is_expression_bool_construction =  self.comparison_branch();
-        let is_last_token = self.current == Some(Token::Then);
-        let is_logic_operator = Lexer::is_some_logic_condition_operator(self.current.as_ref());
+    // negation ::= "NOT" negation | "(" comparisons ")" | comparison
+    fn negation(&mut self) -> ParseResult<Condition> {
+        let span = self.current_span();
 
-        let is_fast_forward = {
-            is_expression_bool_construction &&
-            (is_last_token || is_logic_operator)
-        };
+        match self.current {
+            Some(Token::Not) => {
+                self.next_token();
 
-        if is_fast_forward {
-            return;
-        }
+                let operand = self.negation()?;
+                let span = span.to(operand.span());
 
-        self.equals();
+                Ok(Condition::Not { operand: Box::new(operand), span })
+            },
+            Some(Token::LeftParen) => {
+                // "(" opens either a group of conditions or an arithmetic expression like "(x + 1) > 2",
+                // try the latter first and fall back to the group
+                let checkpoint = self.checkpoint();
 
-        self.comparison_branch();
-    }
+                if let Ok(condition) = self.comparison() {
+                    return Ok(condition);
+                }
+                self.restore(checkpoint);
 
-    fn comparison_branch(&mut self) -> bool {
-        match &self.current {
-            Some(Token::Identifier(variable, IDENTIFIER_ID)) => {
-                self.check_identifier_from_string(variable.to_string());
-                self.expression();
-            }
-            Some(Token::Bool(_, BOOL_ID)) => {
-                self.next_token();
-                return true;
-            },
-            Some(Token::Not) => {
                 self.next_token();
-                self.boolean();
-                return true;
+                let condition = self.comparisons()?;
+                self.match_token(Token::RightParen)?;
+
+                Ok(condition)
             },
-            _ => self.next_token(),
+            _ => self.comparison(),
         }
-        false
     }
 
-    // value ::= identifier | string | number | bool
-    fn value(&mut self) {
-        match &self.current {
-            Some(Token::Identifier(identifier, id)) if *id == IDENTIFIER_ID => {
-                self.check_identifier_from_string(identifier.to_string());
-                self.next_token(); return;
-            },
-            Some(Token::String(_, id))
-            | Some(Token::Number(_, id))
-            | Some(Token::Bool(_, id)) if 
-                *id == IDENTIFIER_ID
-                || *id == STRING_ID
-                || *id == NUMBER_ID
-                || *id == BOOL_ID => { 
-                    self.next_token(); return;
-            },
-            _ => {}
+    // comparison ::= (expression equals expression) | boolean
+    fn comparison(&mut self) -> ParseResult<Condition> {
+        let left = self.expression()?;
+
+        // boolean ::= identifier | bool | call
+        if !Lexer::is_some_equality_operator(self.current.as_ref()) {
+            match &left {
+                Expression::Value { value: value @ (Token::Identifier(..) | Token::Bool(..)), span } => {
+                    return Ok(Condition::Value { value: value.clone(), span: *span });
+                }
+                // the SUB has to return a Bool then
+                Expression::Call { span, .. } => {
+                    let right = Expression::Value { value: Token::Bool(true, BOOL_ID), span: *span };
+                    let span = *span;
+
+                    return Ok(Condition::Comparison { left, operator: Token::Equals, right, span });
+                }
+                _ => {}
+            }
         }
 
-        Self::abort(
-            format!(
-                "Expected one of: {:#?}, {:#?}, {:#?}, or {:#?}. Got: {:#?}",
-                Token::Identifier(PLACEHOLDER, IDENTIFIER_ID),
-                Token::String(PLACEHOLDER, STRING_ID),
-                Token::Number(0, NUMBER_ID),
-                Token::Bool(false, BOOL_ID),
-                self.current
-            )
-        );
+        let operator = self.equals()?;
+        let right = self.expression()?;
+        let span = left.span().to(right.span());
+
+        Ok(Condition::Comparison { left, operator, right, span })
     }
 
     // expression ::= term {("+" | "-") term}
-    fn expression(&mut self) {
-        self.term();
+    fn expression(&mut self) -> ParseResult<Expression> {
+        let mut expression = self.term()?;
 
         while self.current == Some(Token::Plus) || self.current == Some(Token::Minus) {
+            let operator = self.current.clone().unwrap();
             self.next_token();
 
-            self.term();
+            let right = self.term()?;
+            expression = Expression::binary(expression, operator, right);
         }
+        Ok(expression)
     }
 
     // term ::= unary {("*" | "/" | "%") unary}
-    fn term(&mut self) {
-        self.unary();
+    fn term(&mut self) -> ParseResult<Expression> {
+        let mut expression = self.unary()?;
 
         while self.current == Some(Token::Times)
         || self.current == Some(Token::Divide)
         || self.current == Some(Token::Modulo) {
+            let operator = self.current.clone().unwrap();
             self.next_token();
 
-            self.unary();
+            let right = self.unary()?;
+            expression = Expression::binary(expression, operator, right);
         }
+        Ok(expression)
     }
 
     // unary ::= ["+" | "-"] primary
-    fn unary(&mut self) {
+    fn unary(&mut self) -> ParseResult<Expression> {
         if self.current == Some(Token::Plus) || self.current == Some(Token::Minus) {
+            let operator = self.current.clone().unwrap();
+            let span = self.current_span();
             self.next_token();
+
+            let operand = self.primary()?;
+            let span = span.to(operand.span());
+
+            return Ok(Expression::Unary { operator, operand: Box::new(operand), span });
         }
 
-        self.primary();
+        self.primary()
     }
 
-    // primary ::= identifier | number
-    fn primary(&mut self) {
-        let mut is_matching = false;
+    // primary ::= call | element | identifier | number | float | string | bool | "(" expression ")"
+    fn primary(&mut self) -> ParseResult<Expression> {
+        let span = self.current_span();
 
         match &self.current {
-            Some(Token::Identifier(identifier, id)) if *id == IDENTIFIER_ID => {
-                self.check_identifier_from_string(identifier.to_string());
-                self.next_token();
-                is_matching = true;
-            },
-            Some(Token::Number(_, id)) if *id == NUMBER_ID => {
+            // element ::= identifier "(" expression ")", for an array set up with DIM
+            Some(Token::Identifier(array, _)) if self.next == Some(Token::LeftParen) && self.arrays.contains(array) => {
                 self.next_token();
-                is_matching = true;
-            },
-            _ => {}
-        }
+                let identifier = self.previous.clone().unwrap();
 
-        if is_matching {
-            return;
-        }
+                self.match_token(Token::LeftParen)?;
+                let index = self.expression()?;
+                self.match_token(Token::RightParen)?;
 
-        Self::abort(
-            format!(
-                "expected {:#?} OR {:#?}, got {:#?}",
-                Token::Identifier(PLACEHOLDER, IDENTIFIER_ID),
-                Token::Number(0, NUMBER_ID),
-                self.current
-            )
-        );
-    }
+                let span = span.to(self.previous_span());
 
-    // boolean ::= identifier | bool
-    fn boolean(&mut self) {
-        match &self.current {
+                return Ok(Expression::Element { identifier, index: Box::new(index), span });
+            },
+            Some(Token::Identifier(..)) if self.next == Some(Token::LeftParen) => {
+                let (name, arguments, span) = self.call()?;
+
+                return Ok(Expression::Call { name, arguments, span });
+            },
             Some(Token::Identifier(identifier, id)) if *id == IDENTIFIER_ID => {
-                self.check_identifier_from_string(identifier.to_string());
+                self.check_identifier_from_string(identifier.to_string())?;
                 self.next_token();
-                return;
+                return Ok(Expression::Value { value: self.previous.clone().unwrap(), span });
             },
-            Some(Token::Bool(_, id)) if *id == BOOL_ID => {
+            Some(Token::Number(..))
+            | Some(Token::BigNumber(..))
+            | Some(Token::Float(..))
+            | Some(Token::String(..))
+            | Some(Token::Bool(..)) => {
                 self.next_token();
-                return;
+                return Ok(Expression::Value { value: self.previous.clone().unwrap(), span });
+            },
+            Some(Token::LeftParen) => {
+                self.next_token();
+                let expression = self.expression()?;
+                self.match_token(Token::RightParen)?;
+
+                return Ok(expression);
             },
             _ => {}
         }
 
-        Self::abort(
+        Err(self.error(
             format!(
-                "expected {:#?} OR {:#?}, got {:#?}",
-                Token::Identifier(PLACEHOLDER, IDENTIFIER_ID),
-                Token::Bool(BOOL_PLACEHOLDER, BOOL_ID),
-                self.current
+                "expected {}, {}, {}, {} or {}, found {}",
+                Self::describe(Some(&Token::Identifier(PLACEHOLDER, IDENTIFIER_ID))),
+                Self::describe(Some(&Token::Number(0, NUMBER_ID))),
+                Self::describe(Some(&Token::String(PLACEHOLDER, STRING_ID))),
+                Self::describe(Some(&Token::Bool(BOOL_PLACEHOLDER, BOOL_ID))),
+                Self::describe(Some(&Token::LeftParen)),
+                Self::describe(self.current.as_ref())
             )
-        );
+        ))
     }
 
     // equals ::= ("==" | "!=" | "<=" | ">=" | ">" | "<")
-    fn equals(&mut self) {
+    fn equals(&mut self) -> ParseResult<Token> {
         match &self.current {
             Some(token) if Lexer::is_equality_operator(token) => {
+                let operator = token.clone();
                 self.next_token();
+                Ok(operator)
             }
             _ => {
-                Self::abort(
+                Err(self.error(
                     format!(
-                        "expected one of: {:#?}, {:#?}, {:#?}, {:#?}, {:#?} or {:#?}, got {:#?} instead",
-                        Some(Token::Equals),
-                        Some(Token::NotEquals),
-                        Some(Token::MoreThan),
-                        Some(Token::MoreThanEquals),
-                        Some(Token::LessThan),
-                        Some(Token::LessThanEquals),
-                        self.current
-                ));
+                        "expected a comparison operator (`==`, `!=`, `>`, `>=`, `<` or `<=`), found {}",
+                        Self::describe(self.current.as_ref())
+                )))
             }
         }
     }
 
     // nl ::= '\n'+
-    fn newline(&mut self) {
+    fn newline(&mut self) -> ParseResult<()> {
         // require one
-        self.match_token(Token::Newline);
+        self.match_token(Token::Newline)?;
 
         // remove unnecessary newlines
         while self.current == Some(Token::Newline) {
             self.next_token();
         }
+        Ok(())
     }
 
     // match current
-    fn match_token(&mut self, expected: Token) {
+    fn match_token(&mut self, expected: Token) -> ParseResult<()> {
         match expected {
             Token::Bool(_, id) | Token::String(_, id) | Token::Number(_, id) | Token::Identifier(_, id) => {
-                self.match_value_token(expected, id);
+                self.match_value_token(expected, id)?;
             }
 
             _ if Some(&expected) != self.current.as_ref() => {
-                    Self::abort(format!("expected {:#?}, got {:#?}", expected, self.current));
+                return Err(self.error(format!(
+                    "expected {}, found {}",
+                    Self::describe(Some(&expected)),
+                    Self::describe(self.current.as_ref())
+                )));
             }
             _ => {}
         }
         self.next_token();
+        Ok(())
     }
 
     // match special cases of tokens - Bool, String, Number, Identifier
     // do not check Identifier - already checked before every function use
-    fn match_value_token(&mut self, expected: Token, id: u8) {
+    fn match_value_token(&mut self, expected: Token, id: u8) -> ParseResult<()> {
         match self.current {
             Some(Token::Bool(_, current_id)) | Some(Token::String(_, current_id)) |
             Some(Token::Number(_, current_id)) | Some(Token::Identifier(_, current_id))
             if id == current_id => {
-                return;
+                return Ok(());
             }
             _ => {}
         }
-        Self::abort(format!("expected {:#?}, got {:#?}", expected, self.current));
+        Err(self.error(format!(
+            "expected {}, found {}",
+            Self::describe(Some(&expected)),
+            Self::describe(self.current.as_ref())
+        )))
+    }
+
+    fn checkpoint(&self) -> (usize, Option<Token>, Option<Token>, Option<Token>) {
+        (self.counter, self.current.clone(), self.next.clone(), self.previous.clone())
+    }
+
+    fn restore(&mut self, checkpoint: (usize, Option<Token>, Option<Token>, Option<Token>)) {
+        (self.counter, self.current, self.next, self.previous) = checkpoint;
     }
 
     fn next_token(&mut self) {
         self.previous.clone_from(&self.current);
         self.current.clone_from(&self.next);
-        self.next = self.tokens.get(self.counter).cloned();
+        self.next = self.tokens.get(self.counter).map(|token| token.node.clone());
         self.counter += 1;
     }
 
-    fn abort(message: String) {
-        panic!("Syntax error: {}", message);
+    // current token sits two places behind the counter, see get_range_of_tokens
+    fn current_span(&self) -> Span {
+        self.counter
+            .checked_sub(2)
+            .and_then(|index| self.tokens.get(index))
+            .or(self.tokens.last())
+            .map(|token| token.span)
+            .unwrap_or_default()
+    }
+
+    fn previous_span(&self) -> Span {
+        self.counter
+            .checked_sub(3)
+            .and_then(|index| self.tokens.get(index))
+            .map(|token| token.span)
+            .unwrap_or_default()
+    }
+
+    fn error(&self, message: String) -> Diagnostic {
+        Diagnostic::error(message, self.current_span())
+    }
+
+    // human readable token for diagnostics
+    fn describe(token: Option<&Token>) -> String {
+        match token {
+            None => "end of file".to_string(),
+            Some(Token::Newline) => "end of line".to_string(),
+            Some(Token::Identifier(name, _)) if name.is_empty() => "an identifier".to_string(),
+            Some(Token::String(value, _)) if value.is_empty() => "a string".to_string(),
+            Some(Token::Identifier(name, _)) => format!("identifier `{}`", name),
+            Some(Token::Label(number)) => format!("line number `{}`", number),
+            Some(Token::Number(..)) if token == Some(&Token::Number(0, NUMBER_ID)) => "a number".to_string(),
+            Some(Token::Bool(..)) if token == Some(&Token::Bool(BOOL_PLACEHOLDER, BOOL_ID)) => "a boolean".to_string(),
+            Some(token) => format!("`{}`", token),
+        }
     }
 }
 
 impl Condition {
-    pub fn get_tokens_from(&self) -> Vec<Token> {
-        Condition::get_tokens_from_operation(&self.operation)
+    fn binary(left: Condition, operator: Token, right: Condition) -> Condition {
+        let span = left.span().to(right.span());
+
+        Condition::Binary { left: Box::new(left), operator, right: Box::new(right), span }
     }
 
-    fn get_tokens_from_operation(o: &Operation) -> Vec<Token> {
-        let mut tokens = vec![];
-        
-        // left
-        if let Some(Operand::Value { negation, value }) = &o.operand_left {
-            tokens.append(&mut Condition::get_tokens_from_negation(*negation));
-            tokens.push(value.clone());
+    pub fn span(&self) -> Span {
+        match self {
+            Condition::Value { span, .. }
+            | Condition::Comparison { span, .. }
+            | Condition::Not { span, .. }
+            | Condition::Binary { span, .. } => *span,
         }
-        if let Some(Operand::Operation { operation }) = &o.operand_left {
-            tokens.append(&mut Condition::get_tokens_from_operation(operation));
+    }
+
+    // "AND" binds stronger than "OR", everything else is a single unit
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Condition::Binary { operator: Token::Or, .. } => 1,
+            Condition::Binary { .. } => 2,
+            Condition::Comparison { .. } | Condition::Not { .. } | Condition::Value { .. } => 3,
+        }
+    }
+}
+
+impl Expression {
+    fn binary(left: Expression, operator: Token, right: Expression) -> Expression {
+        let span = left.span().to(right.span());
+
+        Expression::Binary { left: Box::new(left), operator, right: Box::new(right), span }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Value { span, .. }
+            | Expression::Unary { span, .. }
+            | Expression::Binary { span, .. }
+            | Expression::Call { span, .. }
+            | Expression::Element { span, .. } => *span,
         }
+    }
 
-        // middle
-        if let Some(operator) = &o.operator {
-            tokens.push(operator.clone());
+    // how tightly the expression holds together, higher binds stronger
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Expression::Binary { operator: Token::Plus | Token::Minus, .. } => 1,
+            Expression::Binary { .. } => 2,
+            Expression::Unary { .. } | Expression::Value { .. } | Expression::Call { .. } | Expression::Element { .. } => 3,
         }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+        let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+
+        Parser::new(tokens).parse()
+    }
+
+    fn error_lines(source: &str) -> Vec<usize> {
+        parse(source)
+            .unwrap_err()
+            .iter()
+            .map(|diagnostic| diagnostic.span.line)
+            .collect()
+    }
+
+    #[test]
+    fn parse_a_program() {
+        let source = "LET x = 1\nPRINT x\n";
 
-        // right
-        if let Some(Operand::Value { negation, value }) = &o.operand_right {
-            tokens.append(&mut Condition::get_tokens_from_negation(*negation));
-            tokens.push(value.clone());
+        assert_eq!(parse(source).unwrap().len(), 2);
+    }
+
+    fn let_expression(source: &str) -> Expression {
+        match parse(source).unwrap().pop() {
+            Some(Statement::Let { expression_tree, .. }) => expression_tree,
+            statement => panic!("expected LET, got {:?}", statement),
         }
-        if let Some(Operand::Operation { operation }) = &o.operand_right{
-            tokens.append(&mut Condition::get_tokens_from_operation(operation));
+    }
+
+    // write the tree back with every binary node in parentheses
+    fn nest(expression: &Expression) -> String {
+        match expression {
+            Expression::Value { value, .. } => value.to_string(),
+            Expression::Unary { operator, operand, .. } => format!("{}{}", operator, nest(operand)),
+            Expression::Binary { left, operator, right, .. } => {
+                format!("({} {} {})", nest(left), operator, nest(right))
+            }
+            Expression::Call { name, arguments, .. } => {
+                format!("{}({})", name, arguments.iter().map(nest).collect::<Vec<_>>().join(", "))
+            }
+            Expression::Element { identifier, index, .. } => format!("{}({})", identifier, nest(index)),
         }
+    }
+
+    #[test]
+    fn respect_operator_precedence() {
+        let expression = let_expression("LET y = 1\nLET x = 1 + 2 * y - 6 / 3 % 2\n");
 
-        tokens
+        assert_eq!(nest(&expression), "((1 + (2 * y)) - ((6 / 3) % 2))");
     }
 
-    fn get_tokens_from_negation(is_negation: bool) -> Vec<Token> {
-        if is_negation {
-            return vec![Token::Not];
+    #[test]
+    fn group_with_parentheses() {
+        let expression = let_expression("LET x = -(1 + 2) * (3 - (4 - 5))\n");
+
+        assert_eq!(nest(&expression), "(-(1 + 2) * (3 - (4 - 5)))");
+    }
+
+    #[test]
+    fn span_whole_expression() {
+        let expression = let_expression("LET x = 10 + 2 * 3\n");
+
+        assert_eq!(expression.span(), Span::new(8, 1, 9, 10));
+    }
+
+    #[test]
+    fn report_unclosed_parenthesis() {
+        let diagnostics = parse("LET x = (1 + 2\n").unwrap_err();
+
+        assert_eq!(diagnostics[0].message, "expected `)`, found end of line");
+    }
+
+    fn if_condition(source: &str) -> Condition {
+        match parse(source).unwrap().pop() {
+            Some(Statement::If { condition_tree, .. }) => condition_tree,
+            statement => panic!("expected IF, got {:?}", statement),
         }
-        vec![]
     }
-}
 
-impl Expression {
-    pub fn get_tokens_from(&self) -> Vec<Token> {
-        let mut tokens = vec![];
-        
-        // left
-        if let Some(Term::Value { sign, value }) = &self.left {
-            if sign.is_some() {
-                tokens.push(sign.clone().unwrap());
+    fn nest_condition(condition: &Condition) -> String {
+        match condition {
+            Condition::Value { value, .. } => value.to_string(),
+            Condition::Comparison { left, operator, right, .. } => {
+                format!("[{} {} {}]", nest(left), operator, nest(right))
+            }
+            Condition::Not { operand, .. } => format!("NOT {}", nest_condition(operand)),
+            Condition::Binary { left, operator, right, .. } => {
+                format!("({} {} {})", nest_condition(left), operator, nest_condition(right))
             }
-            tokens.push(value.clone());
         }
-        if let Some(Term::Operation { operation }) = &self.left {
-            tokens.append(&mut Expression::get_tokens_from(operation));
+    }
+
+    const DECLARATIONS: &str = "LET x = 1\nLET y = 2\nLET flag = true\n";
+
+    #[test]
+    fn bind_and_stronger_than_or() {
+        let source = DECLARATIONS.to_string() + "IF x > 1 OR y < 2 AND flag THEN\nENDIF\n";
+
+        assert_eq!(nest_condition(&if_condition(&source)), "([x > 1] OR ([y < 2] AND flag))");
+    }
+
+    #[test]
+    fn negate_a_group() {
+        let source = DECLARATIONS.to_string() + "IF NOT (x > 1 AND y < 2) OR flag THEN\nENDIF\n";
+
+        assert_eq!(nest_condition(&if_condition(&source)), "(NOT ([x > 1] AND [y < 2]) OR flag)");
+    }
+
+    #[test]
+    fn tell_groups_from_arithmetic_parentheses() {
+        let source = DECLARATIONS.to_string() + "IF ((x + 1) * 2 > y) AND NOT NOT (flag) THEN\nENDIF\n";
+
+        assert_eq!(nest_condition(&if_condition(&source)), "([((x + 1) * 2) > y] AND NOT NOT flag)");
+    }
+
+    #[test]
+    fn require_comparison_for_non_boolean_operands() {
+        let source = DECLARATIONS.to_string() + "IF x + 1 THEN\nENDIF\n";
+        let diagnostics = parse(&source).unwrap_err();
+
+        assert_eq!(diagnostics[0].message, "expected a comparison operator (`==`, `!=`, `>`, `>=`, `<` or `<=`), found `THEN`");
+    }
+
+    #[test]
+    fn report_every_broken_line() {
+        let source = "LET x = \nPRINT y\nLET z = 1\nPRINT z z\n";
+
+        assert_eq!(error_lines(source), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn recover_inside_blocks() {
+        let source = "LET x = 1\nWHILE x > 1\nDO\nPRINT ;\nENDWHILE\nPRINT q\n";
+
+        assert_eq!(error_lines(source), vec![4, 6]);
+    }
+
+    #[test]
+    fn check_body_of_a_broken_header() {
+        let source = "LET x = 1\nIF x > THEN\nPRINT y\nENDIF\n";
+
+        assert_eq!(error_lines(source), vec![2, 3]);
+    }
+
+    #[test]
+    fn report_missing_block_terminator() {
+        let source = "WHILE true\nDO\nPRINT 1\n";
+        let diagnostics = parse(source).unwrap_err();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "expected `ENDWHILE`, found end of file");
+    }
+
+    #[test]
+    fn parse_else_branches() {
+        let source = DECLARATIONS.to_string() + "IF x > 1 THEN\nPRINT x\nELSEIF y > 1 THEN\nELSEIF flag THEN\nPRINT y\nELSE\nPRINT 0\nPRINT 1\nENDIF\n";
+
+        match parse(&source).unwrap().pop() {
+            Some(Statement::If { if_body, elseif_branches, else_body, .. }) => {
+                assert_eq!(if_body.len(), 1);
+                assert_eq!(elseif_branches.len(), 2);
+                assert_eq!(nest_condition(&elseif_branches[1].0), "flag");
+                assert_eq!(else_body.len(), 2);
+            }
+            statement => panic!("expected IF, got {:?}", statement),
         }
+    }
 
-        //middle
-        if let Some(operator) = &self.numeric_operator {
-            tokens.push(operator.clone());
+    #[test]
+    fn report_misplaced_else() {
+        let source = DECLARATIONS.to_string() + "ELSE\nIF x > 1 THEN\nELSE\nELSEIF y > 1 THEN\nENDIF\n";
+
+        assert_eq!(error_lines(&source), vec![4, 7]);
+    }
+
+    #[test]
+    fn parse_a_counted_loop() {
+        let source = "FOR i = 10 TO 1 STEP -3\nPRINT i\nNEXT i\nFOR j = 1 TO 2\nNEXT\n";
+        let statements = parse(source).unwrap();
+
+        match &statements[0] {
+            Statement::For { identifier, start, end, step: Some(step), for_body, span } => {
+                assert_eq!(identifier, &Token::Identifier("i".to_string(), IDENTIFIER_ID));
+                assert_eq!((nest(start), nest(end), nest(step)), ("10".to_string(), "1".to_string(), "-3".to_string()));
+                assert_eq!(for_body.len(), 1);
+                assert_eq!(*span, Span::new(0, 1, 1, 23));
+            }
+            statement => panic!("expected FOR, got {:?}", statement),
         }
+        assert!(matches!(&statements[1], Statement::For { step: None, span, .. } if *span == Span::new(39, 4, 1, 14)));
+    }
+
+    #[test]
+    fn close_the_right_loop() {
+        let source = "FOR i = 1 TO 2\nFOR j = 1 TO 2\nNEXT i\nNEXT j\n";
+        let diagnostics = parse(source).unwrap_err();
+
+        assert_eq!(diagnostics[0].message, "`NEXT i` does not close the loop over `j`");
+        assert_eq!(diagnostics[0].span.line, 3);
+    }
 
-        // right
-        if let Some(Term::Value { sign, value }) = &self.right {
-            if sign.is_some() {
-                tokens.push(sign.clone().unwrap());
+    #[test]
+    fn jump_only_inside_loops() {
+        let source = "BREAK\nWHILE true\nDO\nIF true THEN\nCONTINUE\nENDIF\nBREAK\nENDWHILE\nCONTINUE\n";
+        let diagnostics = parse(source).unwrap_err();
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "`BREAK` can only be used inside a loop");
+        assert_eq!(diagnostics[1].span, Span::new(63, 9, 1, 8));
+    }
+
+    #[test]
+    fn parse_subs_and_calls() {
+        let source = "SUB add(a, b)\nLET sum = a + b\nRETURN sum\nENDSUB\nLET x = add(1, add(2, 3)) * 2\nCALL add(x, x)\n";
+        let ast = parse(source).unwrap();
+
+        match &ast[0] {
+            Statement::Sub { parameters, sub_body, .. } => {
+                assert_eq!(parameters.len(), 2);
+                assert!(matches!(sub_body[1], Statement::Return { value: Some(_), .. }));
             }
-            tokens.push(value.clone());
+            statement => panic!("expected SUB, got {:?}", statement),
         }
-        if let Some(Term::Operation { operation }) = &self.right {
-            tokens.append(&mut Expression::get_tokens_from(operation));
+        assert_eq!(nest(&let_expression(&source[..source.find("CALL").unwrap()])), "(add(1, add(2, 3)) * 2)");
+        assert!(matches!(&ast[2], Statement::Call { arguments, .. } if arguments.len() == 2));
+    }
+
+    #[test]
+    fn parse_print_lists() {
+        let ast = parse("LET x = 1\nPRINT \"x = \"; x + 1, x;\nPRINT\nPRINT x,\n").unwrap();
+
+        match &ast[1] {
+            Statement::Print { items } => {
+                let items: Vec<(String, Option<Separator>)> = items
+                    .iter()
+                    .map(|(expression, separator)| (nest(expression), *separator))
+                    .collect();
+
+                assert_eq!(items, vec![
+                    ("\"x = \"".to_string(), Some(Separator::Join)),
+                    ("(x + 1)".to_string(), Some(Separator::Zone)),
+                    ("x".to_string(), Some(Separator::Join)),
+                ]);
+            }
+            statement => panic!("expected PRINT, got {:?}", statement),
         }
+        assert!(matches!(&ast[2], Statement::Print { items } if items.is_empty()));
+        assert!(matches!(&ast[3], Statement::Print { items } if items[0].1 == Some(Separator::Zone)));
+        assert_eq!(error_lines("LET x = 1\nPRINT x;;\nPRINT ,\nPRINT x x\n"), vec![2, 3, 4]);
+    }
 
-        tokens
+    #[test]
+    fn keep_string_functions_names() {
+        let source = "LET s = MID$(\"abc\", 2)\nSUB LEN(a)\nENDSUB\nDIM VAL(3)\nLET LEFT$ = 1\nINPUT \"?\" STR$\n";
+
+        assert_eq!(error_lines(source), vec![2, 4, 5, 6]);
+        assert_eq!(parse(source).unwrap_err()[0].message, "`LEN` is a string function, pick another name");
     }
-}
 
+    #[test]
+    fn point_out_lowercase_keywords() {
+        let source = "print 1\nlet x = 1\nx 1\n";
+
+        assert_eq!(error_lines(source), vec![1, 2, 3]);
+        assert_eq!(
+            parse(source).unwrap_err()[0].message,
+            "expected a statement, found identifier `print`, keywords are written in capitals like `PRINT` or the file needs `#!dialect case-insensitive`"
+        );
+        assert_eq!(parse(source).unwrap_err()[2].message, "expected a statement, found identifier `x`");
+    }
+
+    #[test]
+    fn keep_subs_apart() {
+        let source = "LET x = 1\nSUB show(a)\nPRINT x\nRETURN\nENDSUB\nRETURN 1\nSUB show()\nENDSUB\nSUB main()\nENDSUB\nIF x == 1 THEN\nSUB inner()\nENDIF\nLET show = 2\n";
+
+        assert_eq!(error_lines(source), vec![3, 6, 7, 9, 12, 14]);
+    }
+
+    #[test]
+    fn parse_line_numbers() {
+        let source = "10 LET x = 1\n20\nGOSUB 40\nGOTO 20\n40 PRINT x\nRETURN\n";
+        let ast = parse(source).unwrap();
+
+        assert!(matches!(ast[0], Statement::Label { number: 10 }));
+        assert!(matches!(ast[2], Statement::Label { number: 20 }));
+        assert!(matches!(ast[4], Statement::Gosub { label: 40, .. }));
+        assert!(matches!(ast[5], Statement::Goto { label: 20, .. }));
+        assert!(matches!(ast[8], Statement::GosubReturn { .. }));
+    }
+
+    #[test]
+    fn reject_bad_jumps() {
+        let source = "10 PRINT \"a\"\n10 PRINT \"b\"\nIF true THEN\n20 GOSUB 10\nENDIF\nGOTO 20\nGOTO 30\nRETURN 1\nSUB f()\nGOTO 10\nENDSUB\nWHILE true\nDO\nGOSUB 10\nENDWHILE\n";
+        let diagnostics = parse(source).unwrap_err();
+
+        assert_eq!(error_lines(source), vec![2, 6, 7, 8, 10, 14]);
+        assert_eq!(diagnostics[1].message, "line `20` is inside a block, jumps can only go to lines outside of them");
+        assert_eq!(diagnostics[2].span, Span::new(70, 7, 6, 2));
+        assert_eq!(diagnostics[5].message, "`GOSUB` cannot be used inside a loop");
+    }
+
+    #[test]
+    fn parse_arrays() {
+        let source = "DIM a(10)\nLET a(1 + 1) = 5\nLET x = a(a(2)) * f(2)\n";
+        let ast = parse(source).unwrap();
+
+        assert!(matches!(&ast[0], Statement::Dim { size: Expression::Value { value: Token::Number(10, _), .. }, .. }));
+        assert!(matches!(&ast[1], Statement::LetElement { index: Expression::Binary { .. }, .. }));
+        assert_eq!(nest(&let_expression(source)), "(a(a(2)) * f(2))");
+    }
+
+    #[test]
+    fn parse_floats() {
+        let source = "LET x = 1.5 * -0.5 + 2\n";
+        let ast = parse(source).unwrap();
+
+        assert!(matches!(&ast[0], Statement::Let { expression_tree: Expression::Binary { operator: Token::Plus, .. }, .. }));
+        assert_eq!(nest(&let_expression(source)), "((1.5 * -0.5) + 2)");
+    }
+
+    #[test]
+    fn keep_arrays_and_variables_apart() {
+        let source = "LET x = 1\nDIM x(2)\nDIM a(2)\nLET y = a + 1\nLET a = 2\nLET b(0) = 1\nSUB f()\nLET a(0) = 1\nENDSUB\n";
+
+        assert_eq!(error_lines(source), vec![2, 4, 5, 6, 8]);
+    }
+
+    #[test]
+    fn skip_stray_block_terminator() {
+        let source = "ENDIF\nPRINT y\n";
+
+        assert_eq!(error_lines(source), vec![1, 2]);
+    }
+}
@@ -0,0 +1,974 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::builtins::Builtin;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::Token;
+use crate::parser::{Condition, Expression, Statement};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Number,
+    Float,
+    String,
+    Bool,
+}
+
+/// How big a Number can get.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NumberMode {
+    /// an `i32`
+    #[default]
+    Fixed,
+    /// any size at all, chosen with `--bigint`
+    Big,
+}
+
+/// Type of every variable in the program, or of the elements of every array.
+pub type SymbolTable = HashMap<String, Type>;
+
+/// What a SUB takes, what it gives back and the types of its own variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub parameters: Vec<Type>,
+    // None when no RETURN has a value
+    pub returns: Option<Type>,
+    pub variables: SymbolTable,
+    pub arrays: SymbolTable,
+}
+
+/// Types of the main program's variables and arrays, and of every SUB.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    pub variables: SymbolTable,
+    pub arrays: SymbolTable,
+    pub subs: HashMap<String, Signature>,
+    pub numbers: NumberMode,
+}
+
+impl Symbols {
+    /// Variables of a SUB, or of the main program for `None`.
+    pub fn variables_in(&self, scope: Option<&str>) -> &SymbolTable {
+        match scope {
+            Some(sub) => &self.subs[sub].variables,
+            None => &self.variables,
+        }
+    }
+
+    /// Arrays of a SUB, or of the main program for `None`.
+    pub fn arrays_in(&self, scope: Option<&str>) -> &SymbolTable {
+        match scope {
+            Some(sub) => &self.subs[sub].arrays,
+            None => &self.arrays,
+        }
+    }
+
+    /// Type of an expression which has been through the Analyzer.
+    pub fn type_of(&self, expression: &Expression, scope: Option<&str>) -> Option<Type> {
+        match expression {
+            Expression::Value { value, .. } => match value {
+                Token::Identifier(name, _) => self.variables_in(scope).get(name).copied(),
+                Token::Number(..) | Token::BigNumber(..) => Some(Type::Number),
+                Token::Float(..) => Some(Type::Float),
+                Token::String(..) => Some(Type::String),
+                Token::Bool(..) => Some(Type::Bool),
+                _ => None,
+            },
+            Expression::Unary { operand, .. } => self.type_of(operand, scope),
+            Expression::Binary { left, right, .. } => {
+                match (self.type_of(left, scope), self.type_of(right, scope)) {
+                    (Some(Type::String), _) | (_, Some(Type::String)) => Some(Type::String),
+                    (Some(Type::Float), _) | (_, Some(Type::Float)) => Some(Type::Float),
+                    _ => Some(Type::Number),
+                }
+            }
+            Expression::Call { name, .. } => match Builtin::find(&name.to_string()) {
+                Some(builtin) => Some(builtin.returns()),
+                None => self.subs.get(&name.to_string()).and_then(|sub| sub.returns),
+            },
+            Expression::Element { identifier, .. } => self.arrays_in(scope).get(&identifier.to_string()).copied(),
+        }
+    }
+}
+
+// what is known about the type of a variable or a sub-expression
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Known(Type),
+    // e.g. an INPUT target, its first use decides
+    Unknown,
+    SameAs(usize),
+}
+
+// the slots of a SUB, shared by its body and every call to it
+struct SubSlots {
+    parameters: Vec<(String, usize)>,
+    returns: Option<usize>,
+    variables: HashMap<String, usize>,
+    arrays: HashMap<String, usize>,
+}
+
+/// Gives every variable a type from its `LET`/`INPUT` and checks
+/// that values are used accordingly, before any code gets generated.
+pub struct Analyzer {
+    slots: Vec<Slot>,
+    // variables and array elements of the current scope, the main program or a SUB
+    variables: HashMap<String, usize>,
+    arrays: HashMap<String, usize>,
+    subs: HashMap<String, SubSlots>,
+    current_sub: Option<String>,
+    numbers: NumberMode,
+    diagnostics: Vec<Diagnostic>,
+    // SUB parameters which some call passes a Float to, by position; they are Floats from the start
+    // so that Numbers passed to them widen wherever the calls are
+    float_parameters: HashSet<(String, usize)>,
+}
+
+// nothing tells what was typed in, so keep it as text
+const DEFAULT_TYPE: Type = Type::String;
+
+impl Analyzer {
+    pub fn new() -> Analyzer {
+        Self::with_numbers(NumberMode::default())
+    }
+
+    pub fn with_numbers(numbers: NumberMode) -> Analyzer {
+        Analyzer {
+            slots: Vec::new(),
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            subs: HashMap::new(),
+            current_sub: None,
+            numbers,
+            diagnostics: Vec::new(),
+            float_parameters: HashSet::new(),
+        }
+    }
+
+    pub fn analyze(&mut self, statements: &[Statement]) -> Result<Symbols, Vec<Diagnostic>> {
+        // once more from scratch for every parameter found to be a Float, the earlier calls may have passed Numbers
+        loop {
+            let float_parameters = self.float_parameters.len();
+
+            self.check_program(statements);
+
+            if self.float_parameters.len() == float_parameters {
+                break;
+            }
+            *self = Analyzer {
+                float_parameters: std::mem::take(&mut self.float_parameters),
+                ..Self::with_numbers(self.numbers)
+            };
+        }
+
+        if !self.diagnostics.is_empty() {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+
+        let subs = self.subs
+            .iter()
+            .map(|(name, sub)| {
+                let signature = Signature {
+                    parameters: sub.parameters
+                        .iter()
+                        .map(|(_, slot)| self.resolve(*slot).unwrap_or(DEFAULT_TYPE))
+                        .collect(),
+                    returns: sub.returns.map(|slot| self.resolve(slot).unwrap_or(DEFAULT_TYPE)),
+                    variables: self.symbol_table(&sub.variables),
+                    arrays: self.symbol_table(&sub.arrays),
+                };
+                (name.to_string(), signature)
+            })
+            .collect();
+
+        Ok(Symbols {
+            variables: self.symbol_table(&self.variables),
+            arrays: self.symbol_table(&self.arrays),
+            subs,
+            numbers: self.numbers,
+        })
+    }
+
+    fn check_program(&mut self, statements: &[Statement]) {
+        // a SUB may be called before it is defined
+        for statement in statements {
+            if let Statement::Sub { name: Token::Identifier(name, _), parameters, sub_body } = statement {
+                let mut parameter_slots = vec![];
+
+                for (position, parameter) in parameters.iter().enumerate() {
+                    if let Token::Identifier(parameter, _) = parameter {
+                        let slot = match self.float_parameters.contains(&(name.to_string(), position)) {
+                            true => Slot::Known(Type::Float),
+                            false => Slot::Unknown,
+                        };
+                        parameter_slots.push((parameter.to_string(), self.new_slot(slot)));
+                    }
+                }
+                let returns = Self::returns_value(sub_body).then(|| self.new_slot(Slot::Unknown));
+
+                self.subs.insert(name.to_string(), SubSlots {
+                    parameters: parameter_slots,
+                    returns,
+                    variables: HashMap::new(),
+                    arrays: HashMap::new(),
+                });
+            }
+        }
+
+        self.check_block(statements);
+    }
+
+    fn symbol_table(&self, variables: &HashMap<String, usize>) -> SymbolTable {
+        variables
+            .iter()
+            .map(|(name, slot)| (name.to_string(), self.resolve(*slot).unwrap_or(DEFAULT_TYPE)))
+            .collect()
+    }
+
+    fn returns_value(statements: &[Statement]) -> bool {
+        statements.iter().any(|statement| match statement {
+            Statement::Return { value, .. } => value.is_some(),
+            Statement::If { if_body, elseif_branches, else_body, .. } => {
+                Self::returns_value(if_body)
+                    || elseif_branches.iter().any(|(_, body)| Self::returns_value(body))
+                    || Self::returns_value(else_body)
+            }
+            Statement::While { while_body: body, .. } | Statement::For { for_body: body, .. } => {
+                Self::returns_value(body)
+            }
+            _ => false,
+        })
+    }
+
+    fn check_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Break
+            | Statement::Continue
+            | Statement::Label { .. }
+            | Statement::Goto { .. }
+            | Statement::Gosub { .. }
+            | Statement::GosubReturn { .. }
+            | Statement::Empty => {}
+            Statement::Print { items } => {
+                for (expression, _) in items {
+                    self.infer(expression);
+                }
+            }
+            Statement::Let { identifier: Token::Identifier(name, _), expression_tree } => {
+                let value = self.infer(expression_tree);
+
+                let Some(&variable) = self.variables.get(name) else {
+                    self.variables.insert(name.to_string(), value);
+                    return;
+                };
+
+                if self.widen(variable, value).is_err() {
+                    self.error(
+                        format!(
+                            "cannot assign {} to `{}`, which holds a {}",
+                            self.describe(value),
+                            name,
+                            self.describe(variable)
+                        ),
+                        expression_tree.span(),
+                    );
+                }
+            }
+            Statement::Input { identifier: Token::Identifier(name, _), .. } => {
+                if !self.variables.contains_key(name) {
+                    let slot = self.new_slot(Slot::Unknown);
+                    self.variables.insert(name.to_string(), slot);
+                }
+            }
+            Statement::If { condition_tree, if_body, elseif_branches, else_body } => {
+                self.check_condition(condition_tree);
+                self.check_block(if_body);
+
+                for (condition, body) in elseif_branches {
+                    self.check_condition(condition);
+                    self.check_block(body);
+                }
+                self.check_block(else_body);
+            }
+            Statement::While { condition_tree, while_body } => {
+                self.check_condition(condition_tree);
+                self.check_block(while_body);
+            }
+            Statement::For { identifier: Token::Identifier(name, _), start, end, step, for_body, .. } => {
+                for expression in [Some(start), Some(end), step.as_ref()].into_iter().flatten() {
+                    let slot = self.infer(expression);
+
+                    if let Err(found) = self.expect(slot, Type::Number) {
+                        self.error(format!("FOR loops count with Numbers, found {}", found), expression.span());
+                    }
+                }
+
+                if let Some(Expression::Value { value: Token::Number(0, _), span }) = step {
+                    self.error("the STEP of a FOR loop must not be 0".to_string(), *span);
+                }
+
+                match self.variables.get(name) {
+                    Some(&variable) => {
+                        if let Err(found) = self.expect(variable, Type::Number) {
+                            self.error(format!("cannot count with `{}`, which holds a {}", name, found), start.span());
+                        }
+                    }
+                    None => {
+                        let slot = self.new_slot(Slot::Known(Type::Number));
+                        self.variables.insert(name.to_string(), slot);
+                    }
+                }
+
+                self.check_block(for_body);
+            }
+            Statement::Sub { name: Token::Identifier(name, _), sub_body, .. } => {
+                // the body sees its parameters and nothing of the main program
+                let parameters = self.subs[name].parameters.iter().cloned().collect();
+                let outer = std::mem::replace(&mut self.variables, parameters);
+                let outer_arrays = std::mem::take(&mut self.arrays);
+
+                self.current_sub = Some(name.to_string());
+                self.check_block(sub_body);
+                self.current_sub = None;
+
+                let variables = std::mem::replace(&mut self.variables, outer);
+                let arrays = std::mem::replace(&mut self.arrays, outer_arrays);
+                if let Some(sub) = self.subs.get_mut(name) {
+                    sub.variables = variables;
+                    sub.arrays = arrays;
+                }
+            }
+            Statement::Dim { identifier: Token::Identifier(name, _), size } => {
+                let slot = self.infer(size);
+
+                if let Err(found) = self.expect(slot, Type::Number) {
+                    self.error(format!("the size of an array must be a Number, found {}", found), size.span());
+                }
+
+                if !self.arrays.contains_key(name) {
+                    let slot = self.new_slot(Slot::Unknown);
+                    self.arrays.insert(name.to_string(), slot);
+                }
+            }
+            Statement::LetElement { identifier: Token::Identifier(name, _), index, expression_tree } => {
+                let element = self.check_element(name, index);
+                let value = self.infer(expression_tree);
+
+                if self.widen(element, value).is_err() {
+                    let message = format!(
+                        "cannot assign {} to an element of `{}`, which holds {}s",
+                        self.describe(value),
+                        name,
+                        self.describe(element)
+                    );
+                    self.error(message, expression_tree.span());
+                }
+            }
+            Statement::Call { name: Token::Identifier(name, _), arguments, span } => {
+                self.check_call(name, arguments, *span);
+            }
+            Statement::Return { value, span } => {
+                let Some(name) = self.current_sub.clone() else {
+                    return;
+                };
+                let Some(returns) = self.subs[&name].returns else {
+                    return;
+                };
+
+                let Some(value) = value else {
+                    self.error(format!("`{}` returns a value elsewhere, so this RETURN needs one too", name), *span);
+                    return;
+                };
+
+                let slot = self.infer(value);
+
+                if self.widen(returns, slot).is_err() {
+                    let message = format!(
+                        "cannot return {} from `{}`, which returns a {}",
+                        self.describe(slot),
+                        name,
+                        self.describe(returns)
+                    );
+                    self.error(message, value.span());
+                }
+            }
+            Statement::Let { .. }
+            | Statement::Input { .. }
+            | Statement::For { .. }
+            | Statement::Sub { .. }
+            | Statement::Call { .. }
+            | Statement::Dim { .. }
+            | Statement::LetElement { .. } => {}
+        }
+    }
+
+    fn check_condition(&mut self, condition: &Condition) {
+        match condition {
+            Condition::Value { value, span } => {
+                let slot = self.infer(&Expression::Value { value: value.clone(), span: *span });
+
+                if let Err(found) = self.expect(slot, Type::Bool) {
+                    self.error(format!("expected a Bool condition, found {}", found), *span);
+                }
+            }
+            Condition::Comparison { left, operator, right, span } => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                self.unify_unknown(left, right);
+
+                // a Number compares with a Float as if it were one
+                let is_mixed = self.is_numeric(left) && self.is_numeric(right);
+
+                if !is_mixed && self.unify(left, right).is_err() {
+                    let message = format!("cannot compare {} with {}", self.describe(left), self.describe(right));
+                    self.error(message, *span);
+                    return;
+                }
+
+                let is_ordering = !matches!(operator, Token::Equals | Token::NotEquals);
+
+                if is_ordering {
+                    if let Err(found) = self.numeric(left) {
+                        self.error(format!("cannot compare {} values with `{}`", found, operator), *span);
+                    }
+                }
+            }
+            Condition::Not { operand, .. } => self.check_condition(operand),
+            Condition::Binary { left, right, .. } => {
+                self.check_condition(left);
+                self.check_condition(right);
+            }
+        }
+    }
+
+    // type slot of the expression, diagnostics for everything that does not fit
+    fn infer(&mut self, expression: &Expression) -> usize {
+        match expression {
+            Expression::Value { value, span } => match value {
+                Token::Identifier(name, _) => match self.variables.get(name) {
+                    Some(slot) => *slot,
+                    None => self.new_slot(Slot::Unknown),
+                },
+                Token::Number(..) => self.new_slot(Slot::Known(Type::Number)),
+                Token::BigNumber(digits, _) => {
+                    if self.numbers == NumberMode::Fixed {
+                        self.error(format!("`{}` does not fit in a Number, `--bigint` allows whole numbers of any size", digits), *span);
+                    }
+                    self.new_slot(Slot::Known(Type::Number))
+                }
+                Token::Float(..) => self.new_slot(Slot::Known(Type::Float)),
+                Token::String(..) => self.new_slot(Slot::Known(Type::String)),
+                Token::Bool(..) => self.new_slot(Slot::Known(Type::Bool)),
+                _ => self.new_slot(Slot::Unknown),
+            },
+            Expression::Unary { operator, operand, span } => {
+                let operand = self.infer(operand);
+
+                match self.numeric(operand) {
+                    Ok(known) => self.new_slot(Slot::Known(known)),
+                    Err(found) => {
+                        self.error(format!("cannot apply `{}` to {}", operator, found), *span);
+                        self.new_slot(Slot::Known(Type::Number))
+                    }
+                }
+            }
+            Expression::Binary { left, operator, right, span } => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+
+                // "+" also joins Strings, but never a String with anything else
+                let is_string = |slot| self.resolve(slot) == Some(Type::String);
+                if *operator == Token::Plus && (is_string(left) || is_string(right)) {
+                    if self.unify(left, right).is_err() {
+                        let message = format!("cannot apply `+` to {} and {}", self.describe(left), self.describe(right));
+                        self.error(message, *span);
+                    }
+                    return self.new_slot(Slot::Known(Type::String));
+                }
+
+                self.unify_unknown(left, right);
+
+                match (self.numeric(left), self.numeric(right)) {
+                    // one Float makes the whole calculation a Float one
+                    (Ok(Type::Float), Ok(_)) | (Ok(_), Ok(Type::Float)) => self.new_slot(Slot::Known(Type::Float)),
+                    (Ok(_), Ok(_)) => self.new_slot(Slot::Known(Type::Number)),
+                    _ => {
+                        let message = format!(
+                            "cannot apply `{}` to {} and {}",
+                            operator,
+                            self.describe(left),
+                            self.describe(right)
+                        );
+                        self.error(message, *span);
+                        self.new_slot(Slot::Known(Type::Number))
+                    }
+                }
+            }
+            Expression::Call { name, arguments, span } => {
+                let Token::Identifier(name, _) = name else {
+                    return self.new_slot(Slot::Unknown);
+                };
+
+                if let Some(returns) = self.check_call(name, arguments, *span) {
+                    return returns;
+                }
+                if self.subs.contains_key(name) {
+                    self.error(format!("`{}` does not return a value", name), *span);
+                }
+                self.new_slot(Slot::Unknown)
+            }
+            Expression::Element { identifier: Token::Identifier(name, _), index, .. } => self.check_element(name, index),
+            Expression::Element { .. } => self.new_slot(Slot::Unknown),
+        }
+    }
+
+    // the slot of the array's elements
+    fn check_element(&mut self, name: &str, index: &Expression) -> usize {
+        let slot = self.infer(index);
+
+        if let Err(found) = self.expect(slot, Type::Number) {
+            self.error(format!("arrays are indexed with Numbers, found {}", found), index.span());
+        }
+
+        match self.arrays.get(name) {
+            Some(element) => *element,
+            None => self.new_slot(Slot::Unknown),
+        }
+    }
+
+    // the slot of what the SUB returns, if it returns anything
+    fn check_call(&mut self, name: &str, arguments: &[Expression], span: Span) -> Option<usize> {
+        let argument_slots: Vec<usize> = arguments.iter().map(|argument| self.infer(argument)).collect();
+
+        if let Some(builtin) = Builtin::find(name) {
+            self.check_builtin(builtin, &argument_slots, arguments, span);
+            return Some(self.new_slot(Slot::Known(builtin.returns())));
+        }
+
+        let Some(sub) = self.subs.get(name) else {
+            self.error(format!("unknown SUB `{}`", name), span);
+            return None;
+        };
+        let parameters = sub.parameters.clone();
+        let returns = sub.returns;
+
+        if parameters.len() != arguments.len() {
+            let plural = if parameters.len() == 1 { "" } else { "s" };
+            let message = format!(
+                "`{}` takes {} argument{}, found {}",
+                name,
+                parameters.len(),
+                plural,
+                arguments.len()
+            );
+            self.error(message, span);
+            return returns;
+        }
+
+        for (position, (((parameter, parameter_slot), argument_slot), argument)) in
+            parameters.iter().zip(argument_slots).zip(arguments).enumerate()
+        {
+            if self.resolve(*parameter_slot) == Some(Type::Number) && self.resolve(argument_slot) == Some(Type::Float) {
+                self.float_parameters.insert((name.to_string(), position));
+            }
+
+            if self.widen(*parameter_slot, argument_slot).is_err() {
+                let message = format!(
+                    "cannot pass {} as `{}` to `{}`, which takes a {}",
+                    self.describe(argument_slot),
+                    parameter,
+                    name,
+                    self.describe(*parameter_slot)
+                );
+                self.error(message, argument.span());
+            }
+        }
+
+        returns
+    }
+
+    fn check_builtin(&mut self, builtin: Builtin, argument_slots: &[usize], arguments: &[Expression], span: Span) {
+        let parameters = builtin.parameters();
+        let required = parameters.len() - builtin.optional();
+
+        if arguments.len() < required || arguments.len() > parameters.len() {
+            let expected = match builtin.optional() {
+                0 => required.to_string(),
+                _ => format!("{} or {}", required, parameters.len()),
+            };
+            let plural = if parameters.len() == 1 { "" } else { "s" };
+            let message = format!("`{}` takes {} argument{}, found {}", builtin.name(), expected, plural, arguments.len());
+            self.error(message, span);
+            return;
+        }
+
+        for (((parameter, expected), slot), argument) in parameters.iter().zip(argument_slots).zip(arguments) {
+            let found = match (builtin, expected) {
+                // STR$ writes down any Number or Float
+                (Builtin::Str, _) => self.numeric(*slot).err(),
+                (_, expected) => self.expect(*slot, *expected).err(),
+            };
+
+            if let Some(found) = found {
+                let expected = match builtin {
+                    Builtin::Str => "Number or a Float".to_string(),
+                    _ => expected.to_string(),
+                };
+                let message = format!(
+                    "cannot pass {} as `{}` to `{}`, which takes a {}",
+                    found,
+                    parameter,
+                    builtin.name(),
+                    expected
+                );
+                self.error(message, argument.span());
+            }
+        }
+    }
+
+    // on a mismatch returns the type that was found instead
+    fn expect(&mut self, slot: usize, expected: Type) -> Result<(), String> {
+        let expected = self.new_slot(Slot::Known(expected));
+
+        self.unify(slot, expected).map_err(|_| self.describe(slot))
+    }
+
+    // Number or Float, an unknown type becomes a Number
+    fn numeric(&mut self, slot: usize) -> Result<Type, String> {
+        match self.resolve(slot) {
+            Some(Type::Float) => Ok(Type::Float),
+            _ => self.expect(slot, Type::Number).map(|_| Type::Number),
+        }
+    }
+
+    fn is_numeric(&self, slot: usize) -> bool {
+        matches!(self.resolve(slot), Some(Type::Number | Type::Float))
+    }
+
+    // an unknown side takes the numeric type of the other one, e.g. the Float of "x * 1.5"
+    fn unify_unknown(&mut self, left: usize, right: usize) {
+        let is_unknown = |slot| self.resolve(slot).is_none();
+
+        if (is_unknown(left) && self.is_numeric(right)) || (is_unknown(right) && self.is_numeric(left)) {
+            let _ = self.unify(left, right);
+        }
+    }
+
+    // like unify, but a Number may also go where a Float is kept
+    fn widen(&mut self, target: usize, value: usize) -> Result<(), ()> {
+        if self.resolve(target) == Some(Type::Float) && self.resolve(value) == Some(Type::Number) {
+            return Ok(());
+        }
+        self.unify(target, value)
+    }
+
+    fn unify(&mut self, left: usize, right: usize) -> Result<(), ()> {
+        let left = self.find(left);
+        let right = self.find(right);
+
+        if left == right {
+            return Ok(());
+        }
+
+        match (self.slots[left], self.slots[right]) {
+            (Slot::Known(left), Slot::Known(right)) if left != right => Err(()),
+            (Slot::Known(_), _) => {
+                self.slots[right] = Slot::SameAs(left);
+                Ok(())
+            }
+            _ => {
+                self.slots[left] = Slot::SameAs(right);
+                Ok(())
+            }
+        }
+    }
+
+    fn find(&self, slot: usize) -> usize {
+        match self.slots[slot] {
+            Slot::SameAs(other) => self.find(other),
+            _ => slot,
+        }
+    }
+
+    fn resolve(&self, slot: usize) -> Option<Type> {
+        match self.slots[self.find(slot)] {
+            Slot::Known(known) => Some(known),
+            _ => None,
+        }
+    }
+
+    fn new_slot(&mut self, slot: Slot) -> usize {
+        self.slots.push(slot);
+        self.slots.len() - 1
+    }
+
+    fn describe(&self, slot: usize) -> String {
+        match self.resolve(slot) {
+            Some(known) => known.to_string(),
+            None => "a value of unknown type".to_string(),
+        }
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::error(message, span));
+    }
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "Number"),
+            Type::Float => write!(f, "Float"),
+            Type::String => write!(f, "String"),
+            Type::Bool => write!(f, "Bool"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use super::*;
+
+    fn analyze(source: &str) -> Result<SymbolTable, Vec<Diagnostic>> {
+        analyze_all(source).map(|symbols| symbols.variables)
+    }
+
+    fn analyze_all(source: &str) -> Result<Symbols, Vec<Diagnostic>> {
+        let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+
+        Analyzer::new().analyze(&ast)
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        analyze(source)
+            .unwrap_err()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    #[test]
+    fn give_every_variable_a_type() {
+        let symbols = analyze("LET x = 1 + 2\nLET name = \"Bob\"\nLET flag = true\nLET copy = flag\n");
+
+        assert_eq!(symbols.unwrap(), SymbolTable::from([
+            ("x".to_string(), Type::Number),
+            ("name".to_string(), Type::String),
+            ("flag".to_string(), Type::Bool),
+            ("copy".to_string(), Type::Bool),
+        ]));
+    }
+
+    #[test]
+    fn infer_input_types_from_their_use() {
+        let source = "INPUT \"?\" a\nINPUT \"?\" b\nINPUT \"?\" c\nINPUT \"?\" d\nLET e = a\nLET f = b + 1\nIF c THEN\nENDIF\nLET e = \"text\"\n";
+        let symbols = analyze(source).unwrap();
+
+        assert_eq!(symbols["a"], Type::String);
+        assert_eq!(symbols["b"], Type::Number);
+        assert_eq!(symbols["c"], Type::Bool);
+        assert_eq!(symbols["d"], DEFAULT_TYPE);
+    }
+
+    #[test]
+    fn reject_arithmetic_on_strings() {
+        let source = "LET x = \"a\"\nLET y = x + 1\nLET z = - x\n";
+
+        assert_eq!(messages(source), vec![
+            "cannot apply `+` to String and Number",
+            "cannot apply `-` to String",
+        ]);
+    }
+
+    #[test]
+    fn reject_changing_the_type_of_a_variable() {
+        let source = "LET x = 1\nLET x = true\n";
+
+        assert_eq!(messages(source), vec!["cannot assign Bool to `x`, which holds a Number"]);
+    }
+
+    #[test]
+    fn reject_bad_comparisons() {
+        let source = "LET s = \"a\"\nLET n = 1\nIF s < \"b\" OR s == n OR n THEN\nENDIF\n";
+
+        assert_eq!(messages(source), vec![
+            "cannot compare String values with `<`",
+            "cannot compare String with Number",
+            "expected a Bool condition, found Number",
+        ]);
+    }
+
+    #[test]
+    fn reject_bad_for_loops() {
+        let source = "LET s = \"a\"\nFOR i = 1 TO s STEP 0\nNEXT\nFOR s = 1 TO 2\nNEXT\nFOR i = 1 TO 2\nLET i = true\nNEXT\n";
+
+        assert_eq!(messages(source), vec![
+            "FOR loops count with Numbers, found String",
+            "the STEP of a FOR loop must not be 0",
+            "cannot count with `s`, which holds a String",
+            "cannot assign Bool to `i`, which holds a Number",
+        ]);
+    }
+
+    #[test]
+    fn type_subs_from_their_calls() {
+        let source = "LET total = add(1, 2)\nSUB add(a, b)\nLET sum = a + b\nRETURN sum\nENDSUB\nSUB greet(name)\nPRINT name\nENDSUB\nCALL greet(\"Bob\")\n";
+        let symbols = analyze_all(source).unwrap();
+
+        assert_eq!(symbols.variables, SymbolTable::from([("total".to_string(), Type::Number)]));
+        assert_eq!(symbols.subs["add"], Signature {
+            parameters: vec![Type::Number, Type::Number],
+            returns: Some(Type::Number),
+            variables: SymbolTable::from([
+                ("a".to_string(), Type::Number),
+                ("b".to_string(), Type::Number),
+                ("sum".to_string(), Type::Number),
+            ]),
+            arrays: SymbolTable::new(),
+        });
+        assert_eq!(symbols.subs["greet"].parameters, vec![Type::String]);
+        assert_eq!(symbols.subs["greet"].returns, None);
+    }
+
+    #[test]
+    fn reject_bad_calls() {
+        let source = "SUB greet(name)\nIF name == \"\" THEN\nRETURN\nENDIF\nENDSUB\nSUB half(n)\nIF n < 0 THEN\nRETURN\nENDIF\nRETURN n / 2\nENDSUB\nSUB yes()\nRETURN true\nRETURN 1\nENDSUB\nCALL greet(1, 2)\nCALL shout(\"a\")\nLET x = greet(\"Bob\")\nLET y = half(true)\n";
+
+        assert_eq!(messages(source), vec![
+            "`half` returns a value elsewhere, so this RETURN needs one too",
+            "cannot return Number from `yes`, which returns a Bool",
+            "`greet` takes 1 argument, found 2",
+            "unknown SUB `shout`",
+            "`greet` does not return a value",
+            "cannot pass Bool as `n` to `half`, which takes a Number",
+        ]);
+    }
+
+    #[test]
+    fn type_parameters_from_every_call() {
+        let number_first = "SUB f(x)\nRETURN x * 2\nENDSUB\nLET a = f(1)\nLET b = f(1.5)\n";
+        let float_first = "SUB f(x)\nRETURN x * 2\nENDSUB\nLET b = f(1.5)\nLET a = f(1)\n";
+
+        for source in [number_first, float_first] {
+            let symbols = analyze_all(source).unwrap();
+
+            assert_eq!(symbols.subs["f"].parameters, vec![Type::Float]);
+            assert_eq!(symbols.subs["f"].returns, Some(Type::Float));
+            assert_eq!(symbols.variables["a"], Type::Float);
+        }
+
+        let sized = "SUB grid(n)\nDIM cells(n)\nENDSUB\nCALL grid(3)\nCALL grid(2.5)\n";
+
+        // n is a Float because of the second call, the DIM is what cannot take it
+        assert_eq!(messages(sized), vec!["the size of an array must be a Number, found Float"]);
+    }
+
+    #[test]
+    fn type_arrays_from_their_elements() {
+        let source = "DIM a(10)\nDIM names(2)\nDIM unused(1)\nLET a(1) = 5\nLET x = a(a(1))\nINPUT \"?\" n\nLET names(n) = \"Bob\"\n";
+        let symbols = analyze_all(source).unwrap();
+
+        assert_eq!(symbols.arrays, SymbolTable::from([
+            ("a".to_string(), Type::Number),
+            ("names".to_string(), Type::String),
+            ("unused".to_string(), DEFAULT_TYPE),
+        ]));
+        assert_eq!(symbols.variables["x"], Type::Number);
+        assert_eq!(symbols.variables["n"], Type::Number);
+    }
+
+    #[test]
+    fn accept_big_numbers_only_when_asked_to() {
+        let source = "LET x = 3000000000\nLET y = x * 2\nPRINT 12345678901234567890; y\n";
+        let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let symbols = Analyzer::with_numbers(NumberMode::Big).analyze(&ast).unwrap();
+
+        assert_eq!(symbols.variables["y"], Type::Number);
+        assert_eq!(symbols.numbers, NumberMode::Big);
+        assert_eq!(messages(source), vec![
+            "`3000000000` does not fit in a Number, `--bigint` allows whole numbers of any size",
+            "`12345678901234567890` does not fit in a Number, `--bigint` allows whole numbers of any size",
+        ]);
+    }
+
+    #[test]
+    fn reject_bad_elements() {
+        let source = "DIM a(\"ten\")\nLET a(true) = 1\nLET a(0) = \"one\"\n";
+
+        assert_eq!(messages(source), vec![
+            "the size of an array must be a Number, found String",
+            "arrays are indexed with Numbers, found Bool",
+            "cannot assign String to an element of `a`, which holds Numbers",
+        ]);
+    }
+
+    #[test]
+    fn promote_numbers_to_floats() {
+        let source = "LET half = 0.5\nLET n = 3\nLET x = n * half\nLET half = n\nINPUT \"?\" scale\nLET y = scale * 2.5\nSUB f(a)\nRETURN a\nENDSUB\nLET z = f(1.5) + f(1)\n";
+        let symbols = analyze_all(source).unwrap();
+
+        assert_eq!(symbols.variables["x"], Type::Float);
+        assert_eq!(symbols.variables["half"], Type::Float);
+        assert_eq!(symbols.variables["n"], Type::Number);
+        assert_eq!(symbols.variables["scale"], Type::Float);
+        assert_eq!(symbols.subs["f"].parameters, vec![Type::Float]);
+        assert_eq!(symbols.variables["z"], Type::Float);
+    }
+
+    #[test]
+    fn reject_floats_where_numbers_are_kept() {
+        let source = "LET n = 1\nLET n = 0.5\nDIM a(1.5)\nFOR i = 0 TO 2.5\nNEXT\nLET b = 1.5 + true\n";
+
+        assert_eq!(messages(source), vec![
+            "cannot assign Float to `n`, which holds a Number",
+            "the size of an array must be a Number, found Float",
+            "FOR loops count with Numbers, found Float",
+            "cannot apply `+` to Float and Bool",
+        ]);
+    }
+
+    #[test]
+    fn type_string_operations() {
+        let source = "INPUT \"?\" name\nLET greeting = \"Hi \" + name\nLET size = LEN(greeting) + 1\nLET part = MID$(greeting, 2) + LEFT$(name, size)\nLET score = STR$(0.5) + STR$(VAL(name))\n";
+        let symbols = analyze_all(source).unwrap();
+
+        assert_eq!(symbols.variables["name"], Type::String);
+        assert_eq!(symbols.variables["size"], Type::Number);
+        assert_eq!(symbols.variables["part"], Type::String);
+        assert_eq!(symbols.variables["score"], Type::String);
+
+        let source = "LET s = \"a\" + 1\nLET n = LEN(5)\nLET m = MID$(\"abc\")\nLET r = RIGHT$(\"abc\", 1.5)\nLET t = STR$(\"abc\")\nLET u = \"a\" - \"b\"\n";
+
+        assert_eq!(messages(source), vec![
+            "cannot apply `+` to String and Number",
+            "cannot pass Number as `text` to `LEN`, which takes a String",
+            "`MID$` takes 2 or 3 arguments, found 1",
+            "cannot pass Float as `count` to `RIGHT$`, which takes a Number",
+            "cannot pass String as `value` to `STR$`, which takes a Number or a Float",
+            "cannot apply `-` to String and String",
+        ]);
+    }
+
+    #[test]
+    fn point_at_the_bad_code() {
+        let diagnostics = analyze("LET x = true\nLET y = 1 + x * 2\n").unwrap_err();
+
+        assert_eq!(diagnostics[0].span, Span::new(25, 2, 13, 5));
+    }
+}
@@ -0,0 +1,96 @@
+// What LEN, MID$, LEFT$, RIGHT$ and VAL do with text. Like bigint.rs, the interpreter uses this module
+// as it is and the emitter pastes it into the generated program, so it depends on nothing but std.
+//
+// Text is counted in characters, which are Unicode scalar values like Rust's `char`, never in bytes:
+// "héllo" is 5 long and nothing gets cut in the middle of a character. Positions start at 1.
+
+/// How many characters the text has.
+pub fn len(text: &str) -> i32 {
+    i32::try_from(text.chars().count()).unwrap_or(i32::MAX)
+}
+
+/// `length` characters from position `start` on, or all of the rest without a length.
+/// Starting past the end gives empty text.
+pub fn mid(text: &str, start: i32, length: Option<i32>) -> Result<String, String> {
+    if start < 1 {
+        return Err(format!("MID$ counts from 1, found a start of {}", start));
+    }
+
+    let rest = text.chars().skip(start as usize - 1);
+
+    match length {
+        Some(length) => Ok(rest.take(characters("MID$", length)?).collect()),
+        None => Ok(rest.collect()),
+    }
+}
+
+/// The first `count` characters, all of them if there are fewer.
+pub fn left(text: &str, count: i32) -> Result<String, String> {
+    Ok(text.chars().take(characters("LEFT$", count)?).collect())
+}
+
+/// The last `count` characters, all of them if there are fewer.
+pub fn right(text: &str, count: i32) -> Result<String, String> {
+    let skipped = text.chars().count().saturating_sub(characters("RIGHT$", count)?);
+
+    Ok(text.chars().skip(skipped).collect())
+}
+
+/// The whole number at the start of the text, like "-42" of "  -42 apples", without any digits there is none.
+pub fn leading_number(text: &str) -> Option<&str> {
+    let text = text.trim_start();
+    let sign = usize::from(text.starts_with(['+', '-']));
+    let digits = text[sign..].bytes().take_while(u8::is_ascii_digit).count();
+
+    (digits > 0).then(|| &text[..sign + digits])
+}
+
+/// The Number at the start of the text, 0 if there is none, like classic BASIC.
+pub fn val(text: &str) -> Result<i32, String> {
+    match leading_number(text) {
+        Some(number) => number.parse().map_err(|_| format!("{} does not fit in a Number", number)),
+        None => Ok(0),
+    }
+}
+
+fn characters(function: &str, count: i32) -> Result<usize, String> {
+    usize::try_from(count).map_err(|_| format!("{} takes no negative number of characters, found {}", function, count))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_characters_not_bytes() {
+        assert_eq!(len(""), 0);
+        assert_eq!(len("héllo"), 5);
+        assert_eq!(len("🧀 and 🍷"), 7);
+    }
+
+    #[test]
+    fn cut_text_by_characters() {
+        assert_eq!(mid("héllo", 2, Some(3)), Ok("éll".to_string()));
+        assert_eq!(mid("héllo", 2, None), Ok("éllo".to_string()));
+        assert_eq!(mid("héllo", 9, Some(2)), Ok(String::new()));
+        assert_eq!(mid("héllo", 4, Some(9)), Ok("lo".to_string()));
+        assert_eq!(mid("héllo", 0, None), Err("MID$ counts from 1, found a start of 0".to_string()));
+        assert_eq!(left("🧀 and 🍷", 1), Ok("🧀".to_string()));
+        assert_eq!(left("ab", 5), Ok("ab".to_string()));
+        assert_eq!(right("🧀 and 🍷", 3), Ok("d 🍷".to_string()));
+        assert_eq!(right("ab", 5), Ok("ab".to_string()));
+        assert_eq!(right("ab", -1), Err("RIGHT$ takes no negative number of characters, found -1".to_string()));
+    }
+
+    #[test]
+    fn read_leading_numbers() {
+        assert_eq!(val("42"), Ok(42));
+        assert_eq!(val("  -7 dwarves"), Ok(-7));
+        assert_eq!(val("+3.9"), Ok(3));
+        assert_eq!(val("cheese"), Ok(0));
+        assert_eq!(val("- 1"), Ok(0));
+        assert_eq!(val("3000000000"), Err("3000000000 does not fit in a Number".to_string()));
+        assert_eq!(leading_number("12345678901234567890!"), Some("12345678901234567890"));
+    }
+}
@@ -0,0 +1,619 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::{env, fs};
+use primal::emitter::Emitter;
+use primal::interpreter::Interpreter;
+use primal::lexer::Lexer;
+use primal::parser::Parser;
+use primal::semantic::{Analyzer, NumberMode};
+
+// primal source -> Rust source, the way `primal file.roq` does it
+fn emit(source: &str, numbers: NumberMode) -> String {
+    let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let symbols = Analyzer::with_numbers(numbers).analyze(&ast).unwrap();
+
+    Emitter::new(ast, symbols).emit().join("\n")
+}
+
+// the same program with `primal run`, for comparing both backends
+fn interpret(source: &str, input: &str) -> String {
+    let tokens = Lexer::new().tokenize(source.to_string()).unwrap();
+    let ast = Parser::new(tokens).parse().unwrap();
+    let symbols = Analyzer::new().analyze(&ast).unwrap();
+    let mut output = vec![];
+
+    Interpreter::new(symbols, input.as_bytes(), &mut output).run(&ast).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+// compile the emitted code with rustc and run it with the given standard input
+fn compile_and_run(name: &str, source: &str, input: &str) -> String {
+    let output = run_compiled(name, source, input);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn run_compiled(name: &str, source: &str, input: &str) -> Output {
+    run_emitted(name, &emit(source, NumberMode::Fixed), input)
+}
+
+fn run_emitted(name: &str, rust_source: &str, input: &str) -> Output {
+    let directory = env::temp_dir().join(format!("primal-end-to-end-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let source_path = directory.join(format!("{}.rs", name));
+    let binary_path: PathBuf = directory.join(name);
+    fs::write(&source_path, rust_source).unwrap();
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let compilation = Command::new(rustc)
+        .arg("--edition=2021")
+        .arg("-o")
+        .arg(&binary_path)
+        .arg(&source_path)
+        .output()
+        .expect("rustc should be available next to cargo");
+
+    assert!(
+        compilation.status.success(),
+        "generated code does not compile:\n{}\n{}",
+        fs::read_to_string(&source_path).unwrap(),
+        String::from_utf8_lossy(&compilation.stderr)
+    );
+
+    let mut program = Command::new(&binary_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    program.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    program.wait_with_output().unwrap()
+}
+
+#[test]
+fn read_typed_input() {
+    let source = "
+INPUT \"How much cheese?\" cheese
+INPUT \"Is it smelly?\" smelly
+INPUT \"What is it called?\" name
+IF smelly AND name == \"Gouda\" THEN
+    LET cheese = cheese * 2
+ENDIF
+PRINT cheese
+PRINT name
+";
+    let output = compile_and_run("read_typed_input", source, "lots\n21\nmaybe\ntrue\nGouda\n");
+
+    assert_eq!(output, "How much cheese?\n\
+        Please enter a whole number.\n\
+        How much cheese?\n\
+        Is it smelly?\n\
+        Please enter true or false.\n\
+        Is it smelly?\n\
+        What is it called?\n\
+        42\n\
+        Gouda\n");
+}
+
+#[test]
+fn keep_variables_set_inside_blocks() {
+    let source = "
+LET i = 0
+WHILE i < 3
+DO
+    INPUT \"Next?\" last
+    LET i = i + 1
+ENDWHILE
+PRINT last
+";
+    let output = compile_and_run("keep_variables_set_inside_blocks", source, "1\n2\n3\n");
+
+    assert_eq!(output, "Next?\nNext?\nNext?\n3\n");
+}
+
+#[test]
+fn choose_a_branch() {
+    let source = "
+INPUT \"Temperature?\" degrees
+IF degrees < 0 THEN
+    PRINT \"ice\"
+ELSEIF degrees < 100 THEN
+    PRINT \"water\"
+ELSE
+    PRINT \"steam\"
+ENDIF
+";
+
+    assert_eq!(compile_and_run("choose_a_branch_ice", source, "-5\n"), "Temperature?\nice\n");
+    assert_eq!(compile_and_run("choose_a_branch_water", source, "20\n"), "Temperature?\nwater\n");
+    assert_eq!(compile_and_run("choose_a_branch_steam", source, "100\n"), "Temperature?\nsteam\n");
+}
+
+#[test]
+fn count_with_for_loops() {
+    let source = "
+INPUT \"Step?\" step
+FOR i = 1 TO 10 STEP 4
+    PRINT i
+NEXT
+FOR i = 2147483647 TO 2147483646 STEP -1
+    PRINT i
+NEXT
+FOR i = 6 TO 1 STEP step
+    FOR j = 1 TO 2
+        LET i = i - 1
+    NEXT j
+    PRINT i
+NEXT i
+PRINT i
+";
+
+    assert_eq!(
+        compile_and_run("count_with_for_loops", source, "-2\n"),
+        "Step?\n1\n5\n9\n2147483647\n2147483646\n4\n0\n0\n"
+    );
+}
+
+#[test]
+fn leave_loops_early() {
+    let source = "
+INPUT \"Limit?\" limit
+FOR i = 1 TO 20 STEP limit / limit
+    IF i % 3 == 0 THEN
+        CONTINUE
+    ENDIF
+    LET j = 0
+    WHILE true
+    DO
+        LET j = j + 1
+        IF j < i THEN
+            CONTINUE
+        ENDIF
+        BREAK
+    ENDWHILE
+    IF j > limit THEN
+        BREAK
+    ENDIF
+    PRINT j
+NEXT
+";
+
+    assert_eq!(compile_and_run("leave_loops_early", source, "5\n"), "Limit?\n1\n2\n4\n5\n");
+}
+
+#[test]
+fn call_subs() {
+    let source = "
+INPUT \"Who?\" name
+CALL greet(name, 3)
+LET total = add(fib(10), 1)
+PRINT total
+IF is_small(total) THEN
+    PRINT \"small\"
+ENDIF
+SUB greet(who, times)
+    FOR i = 1 TO times
+        IF i == 3 THEN
+            RETURN
+        ENDIF
+        PRINT who
+    NEXT
+ENDSUB
+SUB fib(n)
+    IF n < 2 THEN
+        RETURN n
+    ENDIF
+    RETURN fib(n - 1) + fib(n - 2)
+ENDSUB
+SUB add(a, b)
+    LET sum = a + b
+    RETURN sum
+ENDSUB
+SUB is_small(n)
+    WHILE true
+    DO
+        IF n < 100 THEN
+            RETURN true
+        ENDIF
+        BREAK
+    ENDWHILE
+ENDSUB
+";
+
+    assert_eq!(compile_and_run("call_subs", source, "Ann\n"), "Who?\nAnn\nAnn\n56\nsmall\n");
+}
+
+#[test]
+fn jump_between_numbered_lines() {
+    let source = "
+10 INPUT \"How many?\" n
+20 LET i = 0
+30 GOSUB 100
+40 IF i < n THEN
+45     GOTO 30
+50 ENDIF
+60 FOR j = 1 TO 10 STEP n / n
+70     IF j % 2 == 0 THEN
+80         CONTINUE
+90     ENDIF
+95     IF j > 4 THEN
+96         GOTO 200
+97     ENDIF
+98     PRINT j
+99 NEXT
+100 LET i = i + 1
+110 PRINT i
+120 RETURN
+200 PRINT \"done\"
+";
+
+    assert_eq!(
+        compile_and_run("jump_between_numbered_lines", source, "2\n"),
+        "How many?\n1\n2\n1\n3\ndone\n"
+    );
+}
+
+#[test]
+fn come_back_into_branches_after_gosub() {
+    let source = "
+10 LET i = 0
+20 LET i = i + 1
+30 IF i % 3 == 0 THEN
+40     PRINT \"fizz\"
+50     GOSUB 200
+60     IF i > 5 THEN
+70         GOSUB 300
+80         PRINT \"big\"
+90     ENDIF
+100 ELSEIF i == 2 THEN
+110     GOSUB 300
+120 ELSE
+130     PRINT i
+140 ENDIF
+150 IF i < 7 THEN
+160     GOTO 20
+170 ENDIF
+180 PRINT \"done\"
+190 GOTO 400
+200 PRINT \"sub\"
+210 IF i == 6 THEN
+220     GOSUB 300
+230 ENDIF
+240 RETURN
+300 PRINT \"deep\"; i
+310 RETURN
+400 PRINT \"end\"
+";
+    let expected = "1\ndeep2\nfizz\nsub\n4\n5\nfizz\nsub\ndeep6\ndeep6\nbig\n7\ndone\nend\n";
+
+    assert_eq!(compile_and_run("come_back_into_branches_after_gosub", source, ""), expected);
+    assert_eq!(interpret(source, ""), expected);
+}
+
+#[test]
+fn index_arrays_within_bounds() {
+    let source = "
+INPUT \"Last index?\" last
+DIM squares(last)
+DIM words(1)
+LET words(0) = \"square\"
+FOR i = 0 TO last
+    LET squares(i) = i * i
+NEXT
+LET word = words(0)
+LET i = 2
+LET value = squares(i + 1)
+PRINT word
+PRINT value
+LET value = squares(last + 1)
+PRINT value
+";
+
+    let output = run_compiled("index_arrays_within_bounds", source, "3\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Last index?\nsquare\n9\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Runtime error at line 14: index 4 is out of bounds for `squares`, which has 4 elements\n"
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn mix_numbers_and_floats() {
+    let source = "
+SUB fall(seconds)
+    RETURN 0.5 * 9.81 * seconds * seconds
+ENDSUB
+DIM heights(2)
+FOR t = 0 TO 2
+    LET heights(t) = fall(t)
+NEXT
+LET height = heights(2)
+PRINT height
+LET total = 0.0
+LET total = total + 1
+IF height > 19 AND total == 1 THEN
+    PRINT total
+ENDIF
+INPUT \"How fast?\" speed
+LET speed = speed * 0.25
+PRINT speed
+";
+    let output = compile_and_run("mix_numbers_and_floats", source, "fast\n3.0\n");
+
+    assert_eq!(output, "19.62\n1\nHow fast?\nPlease enter a number.\nHow fast?\n0.75\n");
+}
+
+#[test]
+fn calculate_with_big_numbers() {
+    let source = "
+LET factorial = 1
+FOR i = 1 TO 25
+    LET factorial = factorial * i
+NEXT
+PRINT factorial
+LET big = 123456789012345678901234567890
+LET quotient = big / 1000000007
+PRINT quotient
+LET remainder = big % 1000000007
+PRINT remainder
+DIM digits(2)
+LET digits(2) = - big
+IF digits(2) < factorial THEN
+    LET half = 0.5 * digits(2)
+    PRINT half
+ENDIF
+INPUT \"?\" n
+PRINT n
+DIM too_big(big)
+";
+    let output = run_emitted("calculate_with_big_numbers", &emit(source, NumberMode::Big), "99999999999\n");
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "15511210043330985984000000\n123456788148148161864\n197434842\n-61728394506172840000000000000\n?\n99999999999\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Runtime error at line 20: 123456789012345678901234567890 is too big, FOR loops, array sizes and indices have to fit in 32 bits\n"
+    );
+}
+
+#[test]
+fn report_arithmetic_errors_with_their_line() {
+    let divide = "
+INPUT \"Divisor?\" divisor
+LET total = 12 / 4 % 2 - 5 * - 1
+PRINT total
+LET total = total
+LET total = total / divisor
+PRINT total
+";
+    let output = run_compiled("report_division_by_zero", divide, "0\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Divisor?\n6\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Runtime error at line 6: division by zero\n");
+    assert!(!output.status.success());
+
+    let overflow = "
+LET x = 2147483647
+FOR i = 1 TO 2
+    LET x = x + i
+    PRINT x
+NEXT
+";
+    let output = run_compiled("report_overflow", overflow, "");
+
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Runtime error at line 4: integer overflow\n");
+    assert!(!output.status.success());
+
+    let big = "
+LET zero = 0
+LET x = 123456789012345678901234567890 % zero
+";
+    let output = run_emitted("report_big_division_by_zero", &emit(big, NumberMode::Big), "");
+
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Runtime error at line 3: division by zero\n");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn work_with_strings() {
+    let source = "
+INPUT \"Which cheese?\" cheese
+LET size = LEN(cheese)
+PRINT size
+LET cut = LEFT$(cheese, 3) + \"-\" + MID$(cheese, 4, 2) + \"-\" + RIGHT$(cheese, 2)
+PRINT cut
+LET rest = MID$(cheese, size)
+PRINT rest
+LET n = VAL(\" 41 wheels\") + 1
+LET text = STR$(n) + \" and \" + STR$(2.5)
+PRINT text
+LET cut = MID$(cheese, n - 42)
+";
+    let output = run_compiled("work_with_strings", source, "Gruyère\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Which cheese?\n7\nGru-yè-re\ne\n42 and 2.5\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Runtime error at line 12: MID$ counts from 1, found a start of 0\n");
+
+    let big = "
+LET n = VAL(\"123456789012345678901234567890\") + 1
+LET text = STR$(n)
+LET size = LEN(text)
+PRINT text
+PRINT size
+";
+    let output = run_emitted("work_with_big_strings", &emit(big, NumberMode::Big), "");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "123456789012345678901234567891\n30\n");
+}
+
+#[test]
+fn print_lists() {
+    let source = "
+LET x = 41
+PRINT \"x + 1 = \"; x + 1
+PRINT \"é\", x, \"fourteen chars\", 1
+PRINT \"a\"; \"b\", 2.5; \"{}\"; 2147483647
+PRINT \"no newline \";
+PRINT \"yet\"
+PRINT
+PRINT x,
+";
+    let output = compile_and_run("print_lists", source, "");
+
+    assert_eq!(
+        output,
+        "x + 1 = 42\n\
+         é             41            fourteen chars              1\n\
+         ab            2.5{}2147483647\n\
+         no newline yet\n\
+         \n\
+         41            "
+    );
+
+    // a literal too big for Rust is printed as it is written, with `--bigint`
+    let big = run_emitted("print_lists_big", &emit("PRINT 1; 123456789012345678901234567890\n", NumberMode::Big), "");
+
+    assert_eq!(String::from_utf8_lossy(&big.stdout), "1123456789012345678901234567890\n");
+}
+
+#[test]
+fn read_programs_written_without_spaces() {
+    let source = "
+LET x=5
+WHILE x>=1
+DO
+PRINT x*2-1;\",\";
+LET x=x-2
+ENDWHILE
+IF x<=-1 THEN
+PRINT \"done, x is \";x
+ENDIF
+";
+    let output = compile_and_run("read_programs_written_without_spaces", source, "");
+
+    assert_eq!(output, "9,5,1,done, x is -1\n");
+}
+
+#[test]
+fn print_strings_with_escapes_and_braces() {
+    let source = r#"
+INPUT "What's your {name}?\t" name
+LET s = "{" + name + "}\n\"\\\u{e9}"
+PRINT s; "{}{:?}"
+IF s == "{Bo}
+\"\\é" THEN
+PRINT "it spans
+two lines"
+ENDIF
+"#;
+    let output = compile_and_run("print_strings_with_escapes_and_braces", source, "Bo\n");
+
+    assert_eq!(output, "What's your {name}?\t\n{Bo}\n\"\\é{}{:?}\nit spans\ntwo lines\n");
+}
+
+#[test]
+fn use_rust_keywords_as_names() {
+    let source = "
+SUB match(type, self)
+RETURN type + self
+ENDSUB
+LET fn = 2
+LET loop = \"ok\"
+DIM async(2)
+LET async(1) = match(fn, 3)
+FOR in = 1 TO 2
+LET Self = in
+NEXT in
+INPUT \"?\" _
+PRINT async(1); loop; Self; _
+PRINT async(5)
+";
+    let output = run_compiled("use_rust_keywords_as_names", source, "7\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "?\n5ok27\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Runtime error at line 14: index 5 is out of bounds for `async`, which has 3 elements\n"
+    );
+}
+
+#[test]
+fn use_every_rust_reserved_word_as_a_variable() {
+    // all of Rust's keywords, strict and reserved, up to edition 2024, but true and false, which are primal's too
+    let words = [
+        "as", "break", "const", "continue", "crate", "else", "enum", "extern", "fn", "for", "if", "impl", "in", "let",
+        "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+        "trait", "type", "unsafe", "use", "where", "while", "async", "await", "dyn", "abstract", "become", "box", "do",
+        "final", "macro", "override", "priv", "typeof", "unsized", "virtual", "yield", "try", "gen", "_",
+    ];
+    let mut source = String::new();
+
+    for word in words {
+        source.push_str(&format!("LET {} = 1\n", word));
+    }
+    source.push_str(&format!("PRINT {}\n", words.join(" + ")));
+
+    assert_eq!(compile_and_run("use_every_rust_reserved_word_as_a_variable", &source, ""), format!("{}\n", words.len()));
+}
+
+#[test]
+fn use_prelude_variants_as_names() {
+    let source = "
+SUB Err(Ok)
+RETURN Ok * 2
+ENDSUB
+LET Some = 1
+LET None = \"none\"
+DIM Ok(1)
+LET Ok(1) = Err(Some)
+PRINT Some; None; Ok(1)
+";
+
+    assert_eq!(compile_and_run("use_prelude_variants_as_names", source, ""), "1none2\n");
+}
+
+#[test]
+fn agree_on_variables_which_were_not_set_yet() {
+    let source = "
+SUB show(a)
+IF a > 5 THEN
+LET inner = 2.5
+ENDIF
+PRINT inner
+ENDSUB
+LET x = 1
+IF x > 5 THEN
+LET y = 7
+LET s = \"set\"
+LET b = true
+ENDIF
+PRINT y; \"[\"; s; \"]\"; b
+CALL show(x)
+";
+    let expected = "0[]false\n0\n";
+
+    assert_eq!(compile_and_run("agree_on_variables_which_were_not_set_yet", source, ""), expected);
+    assert_eq!(interpret(source, ""), expected);
+}
+
+#[test]
+fn widen_arguments_whatever_the_order_of_the_calls() {
+    let source = "
+SUB twice(x)
+RETURN x * 2
+ENDSUB
+PRINT twice(1); \" \"; twice(1.5)
+";
+
+    assert_eq!(compile_and_run("widen_arguments_whatever_the_order_of_the_calls", source, ""), "2 3\n");
+    assert_eq!(interpret(source, ""), "2 3\n");
+}